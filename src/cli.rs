@@ -0,0 +1,128 @@
+use crate::errors::ErrorFormat;
+
+pub const USAGE: &str = "\
+Usage: rusp [options] [file]
+
+Starts the REPL when no file is given.
+
+Options:
+    --interpret           Run the file with the interpreter (default)
+    --check               Only report syntax errors and warnings, exits with 1 on errors
+    --emit <tokens|ast>   Print the tokens or the syntax tree of the file instead of running it
+    --error-format <human|json>
+                          Print diagnostics as colored snippets (default) or as JSON
+    -h, --help            Print this message
+    -V, --version         Print the version";
+
+#[derive(Debug)]
+pub enum Command {
+    Run(Options),
+    Help,
+    Version,
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    pub script_path: Option<String>,
+    pub emit: Option<Emit>,
+    pub check: bool,
+    pub error_format: ErrorFormat,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Emit {
+    Tokens,
+    Ast,
+}
+
+/// Parses the command line arguments, without the program name.
+pub fn parse_args(args: impl IntoIterator<Item=String>) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--interpret" => (),
+            "--check" => options.check = true,
+            "--emit" => {
+                options.emit = match args.next().as_deref() {
+                    Some("tokens") => Some(Emit::Tokens),
+                    Some("ast") => Some(Emit::Ast),
+                    Some(other) => return Err(format!("unknown --emit kind '{}'", other)),
+                    None => return Err("--emit requires a value".to_owned()),
+                }
+            }
+            "--error-format" => {
+                options.error_format = match args.next().as_deref() {
+                    Some("human") => ErrorFormat::Human,
+                    Some("json") => ErrorFormat::Json,
+                    Some(other) => return Err(format!("unknown --error-format '{}'", other)),
+                    None => return Err("--error-format requires a value".to_owned()),
+                }
+            }
+            flag if flag.starts_with('-') => return Err(format!("unknown option '{}'", flag)),
+            _ if options.script_path.is_some() => return Err("only one file can be given".to_owned()),
+            _ => options.script_path = Some(arg),
+        }
+    }
+
+    if options.emit.is_some() && options.script_path.is_none() {
+        return Err("--emit requires a file".to_owned());
+    }
+    if options.check && options.script_path.is_none() {
+        return Err("--check requires a file".to_owned());
+    }
+    if options.check && options.emit.is_some() {
+        return Err("--check and --emit can't be used together".to_owned());
+    }
+    Ok(Command::Run(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn options(args: &[&str]) -> Options {
+        match parse(args) {
+            Ok(Command::Run(options)) => options,
+            result => panic!("expected options, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn valid_combinations() {
+        let o = options(&[]);
+        assert!(o.script_path.is_none() && o.emit.is_none() && !o.check);
+
+        let o = options(&["--interpret", "script.rsp"]);
+        assert_eq!(o.script_path.as_deref(), Some("script.rsp"));
+
+        let o = options(&["--emit", "ast", "script.rsp", "--error-format", "json"]);
+        assert!(matches!(o.emit, Some(Emit::Ast)));
+        assert_eq!(o.error_format, ErrorFormat::Json);
+
+        let o = options(&["script.rsp", "--check"]);
+        assert!(o.check);
+
+        assert!(matches!(parse(&["--help", "--unknown"]), Ok(Command::Help)));
+        assert!(matches!(parse(&["-V"]), Ok(Command::Version)));
+    }
+
+    #[test]
+    fn invalid_combinations() {
+        assert_eq!(parse(&["--frobnicate"]).unwrap_err(), "unknown option '--frobnicate'");
+        assert_eq!(parse(&["--emit", "bytecode", "a.rsp"]).unwrap_err(), "unknown --emit kind 'bytecode'");
+        assert_eq!(parse(&["a.rsp", "--emit"]).unwrap_err(), "--emit requires a value");
+        assert_eq!(parse(&["--emit", "tokens"]).unwrap_err(), "--emit requires a file");
+        assert_eq!(parse(&["--error-format", "xml"]).unwrap_err(), "unknown --error-format 'xml'");
+        assert_eq!(parse(&["a.rsp", "b.rsp"]).unwrap_err(), "only one file can be given");
+        assert_eq!(parse(&["--check"]).unwrap_err(), "--check requires a file");
+        assert_eq!(parse(&["--check", "--emit", "ast", "a.rsp"]).unwrap_err(), "--check and --emit can't be used together");
+    }
+}
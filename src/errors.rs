@@ -1,28 +1,61 @@
-use codespan_reporting::diagnostic::{Diagnostic, Label};
+use std::cell::{Cell, RefCell};
+use std::ops::Range;
+
+use codespan_reporting::diagnostic::{Diagnostic, Label, LabelStyle, Severity};
 use codespan_reporting::files::SimpleFiles;
 use codespan_reporting::term;
 use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
 
 use crate::interpreter::{InterpreterError, InterpreterErrorWithSpan};
-use crate::lexer::LexerError;
-use crate::parser::ParserError;
+use crate::lexer::{ascii_lookalike, LexerError};
+use crate::parser::{self, ParserError};
+use crate::RunError;
 
 pub fn show_lexer_error<Name, Source>(error: LexerError, file_id: usize, files: SimpleFiles<Name, Source>)
+    where Name: std::fmt::Display + Clone,
+          Source: AsRef<str> {
+    let diagnostic = lexer_diagnostic(error, file_id, &files);
+    emit(&[diagnostic], file_id, &files);
+}
+
+fn lexer_diagnostic<Name, Source>(error: LexerError, file_id: usize, files: &SimpleFiles<Name, Source>) -> Diagnostic<usize>
     where Name: std::fmt::Display + Clone,
           Source: AsRef<str> {
     match error {
         LexerError::UnexpectedCharacter(span) => {
-            let diagnostic = Diagnostic::error()
-                .with_code("E0001")
-                .with_message("Developer has suboptimal IQ")
-                .with_labels(vec![
-                    Label::primary(file_id, span).with_message("Learn the language syntax, you dumbass!")
-                ]);
+            let character = files.get(file_id)
+                .ok()
+                .and_then(|file| file.source().as_ref()[span.clone()].chars().next());
 
-            let writer = StandardStream::stderr(ColorChoice::Always);
-            let config = codespan_reporting::term::Config::default();
+            let mut labels = vec![Label::primary(file_id, span.clone()).with_message("unexpected character")];
+            if let Some(ascii) = character.and_then(ascii_lookalike) {
+                labels.push(Label::secondary(file_id, span).with_message(format!("did you mean '{}'?", ascii)));
+            }
 
-            let _ = term::emit(&mut writer.lock(), &config, &files, &diagnostic);
+            let message = match character {
+                Some(c) => format!("unexpected character '{}' (U+{:04X})", c, c as u32),
+                None => "unexpected character".to_owned(),
+            };
+            Diagnostic::error()
+                .with_code("E0001")
+                .with_message(message)
+                .with_labels(labels)
+        }
+        LexerError::NumberOutOfRange(span) => {
+            let literal = files.get(file_id)
+                .map(|file| file.source().as_ref()[span.clone()].to_owned())
+                .unwrap_or_default();
+
+            let (message, notes) = match literal.parse::<i64>() {
+                _ if literal.ends_with('L') => ("this number doesn't fit in a long", vec![]),
+                Ok(_) => ("this number doesn't fit in an integer", vec![format!("write {}L to make it a long", literal)]),
+                Err(_) => ("this number doesn't fit in an integer", vec![]),
+            };
+            Diagnostic::error()
+                .with_code("E0024")
+                .with_message("number literal out of range")
+                .with_labels(vec![Label::primary(file_id, span).with_message(message)])
+                .with_notes(notes)
         }
     }
 }
@@ -44,18 +77,49 @@ pub fn show_parser_error<Name, Source>(error: ParserError, file_id: usize, files
                 .with_code("E0002")
                 .with_message("unexpected end of file")
         }
+        ParserError::DuplicateParameter(span) => {
+            Diagnostic::error()
+                .with_code("E0016")
+                .with_message("duplicate parameter name")
+                .with_labels(vec![
+                    Label::primary(file_id, span).with_message("this name is already used by another parameter")
+                ])
+        }
+        ParserError::DanglingElse(span) => {
+            Diagnostic::error()
+                .with_code("E0017")
+                .with_message("else without a preceding if")
+                .with_labels(vec![
+                    Label::primary(file_id, span).with_message("this else doesn't belong to any if")
+                ])
+        }
+        ParserError::BreakOutsideLoop(span) => break_outside_loop(file_id, span),
+        ParserError::ContinueOutsideLoop(span) => continue_outside_loop(file_id, span),
+        ParserError::WrongOperatorArity { span, operator } => {
+            let expected = match parser::operator_arity(&operator) {
+                Some(arity) if arity.start() == arity.end() => format!("exactly {}", arity.start()),
+                Some(arity) => format!("at least {}", arity.start()),
+                None => "another number of".to_owned(),
+            };
+            let plural = if expected.ends_with(" 1") { "" } else { "s" };
+            Diagnostic::error()
+                .with_code("E0020")
+                .with_message(format!("wrong number of arguments for '{}'", operator))
+                .with_labels(vec![
+                    Label::primary(file_id, span).with_message(format!("'{}' takes {} argument{}", operator, expected, plural))
+                ])
+        }
     };
 
-    let writer = StandardStream::stderr(ColorChoice::Always);
-    let config = codespan_reporting::term::Config::default();
-
-    let _ = term::emit(&mut writer.lock(), &config, &files, &diagnostic);
+    emit(&[diagnostic], file_id, &files);
 }
 
 pub fn show_interpreter_error<Name, Source>(error: InterpreterErrorWithSpan, file_id: usize, files: SimpleFiles<Name, Source>)
     where Name: std::fmt::Display + Clone,
           Source: AsRef<str> {
     let diagnostic = match error.error {
+        // The script asked to stop, there is nothing to report
+        InterpreterError::Exit(_) => return,
         InterpreterError::VariableNotFound(name) => {
             Diagnostic::error()
                 .with_code("E0003")
@@ -96,10 +160,314 @@ pub fn show_interpreter_error<Name, Source>(error: InterpreterErrorWithSpan, fil
                     Label::primary(file_id, error.span.unwrap()).with_message("not a function")
                 ])
         }
+        InterpreterError::DivisionByZero => {
+            Diagnostic::error()
+                .with_code("E0008")
+                .with_message("division by zero")
+                .with_labels(vec![
+                    Label::primary(file_id, error.span.unwrap()).with_message("division by zero")
+                ])
+        }
+        InterpreterError::RecursionLimitExceeded => {
+            Diagnostic::error()
+                .with_code("E0009")
+                .with_message("recursion limit exceeded")
+                .with_labels(vec![
+                    Label::primary(file_id, error.span.unwrap()).with_message("recursion limit exceeded")
+                ])
+        }
+        InterpreterError::IndexOutOfBounds => {
+            Diagnostic::error()
+                .with_code("E0010")
+                .with_message("index out of bounds")
+                .with_labels(vec![
+                    Label::primary(file_id, error.span.unwrap()).with_message("index out of bounds")
+                ])
+        }
+        InterpreterError::InvalidIndex => {
+            Diagnostic::error()
+                .with_code("E0011")
+                .with_message("invalid index")
+                .with_labels(vec![
+                    Label::primary(file_id, error.span.unwrap()).with_message("invalid index")
+                ])
+        }
+        InterpreterError::KeyNotFound => {
+            Diagnostic::error()
+                .with_code("E0012")
+                .with_message("key not found")
+                .with_labels(vec![
+                    Label::primary(file_id, error.span.unwrap()).with_message("key not found")
+                ])
+        }
+        InterpreterError::AssertionFailed(message) => {
+            Diagnostic::error()
+                .with_code("E0013")
+                .with_message(message)
+                .with_labels(vec![
+                    Label::primary(file_id, error.span.unwrap()).with_message("assertion failed")
+                ])
+        }
+        InterpreterError::ParseError(text) => {
+            Diagnostic::error()
+                .with_code("E0014")
+                .with_message(format!("cannot parse '{}' as a number", text))
+                .with_labels(vec![
+                    Label::primary(file_id, error.span.unwrap()).with_message("parse error")
+                ])
+        }
+        // Only reachable from a syntax tree that didn't go through the parser
+        InterpreterError::Break => break_outside_loop(file_id, error.span.unwrap()),
+        InterpreterError::Continue => continue_outside_loop(file_id, error.span.unwrap()),
+        InterpreterError::IntegerOverflow(operation) => {
+            Diagnostic::error()
+                .with_code("E0021")
+                .with_message(format!("integer overflow in '{}'", operation))
+                .with_labels(vec![
+                    Label::primary(file_id, error.span.unwrap()).with_message("the result doesn't fit in an integer")
+                ])
+        }
+        InterpreterError::ImportCycle(path) => {
+            Diagnostic::error()
+                .with_code("E0022")
+                .with_message(format!("'{}' is already being imported", path))
+                .with_labels(vec![
+                    Label::primary(file_id, error.span.unwrap()).with_message("import cycle")
+                ])
+        }
+        InterpreterError::ImportFailed { path, source, error: import_error } => {
+            let mut import_files = SimpleFiles::new();
+            let import_file_id = import_files.add(path.clone(), source);
+            match *import_error {
+                RunError::LexerError(e) => show_lexer_error(e, import_file_id, import_files),
+                RunError::ParserError(e) => show_parser_error(e, import_file_id, import_files),
+                RunError::InterpreterError(e) => show_interpreter_error(e, import_file_id, import_files),
+            }
+
+            Diagnostic::error()
+                .with_code("E0023")
+                .with_message(format!("cannot import '{}'", path))
+                .with_labels(vec![
+                    Label::primary(file_id, error.span.unwrap()).with_message("imported here")
+                ])
+        }
+        InterpreterError::IoError(message) => {
+            Diagnostic::error()
+                .with_code("E0015")
+                .with_message(message)
+                .with_labels(vec![
+                    Label::primary(file_id, error.span.unwrap()).with_message("I/O error")
+                ])
+        }
     };
 
-    let writer = StandardStream::stderr(ColorChoice::Always);
-    let config = codespan_reporting::term::Config::default();
+    emit(&[diagnostic], file_id, &files);
+}
 
-    let _ = term::emit(&mut writer.lock(), &config, &files, &diagnostic);
-}
\ No newline at end of file
+fn break_outside_loop(file_id: usize, span: Range<usize>) -> Diagnostic<usize> {
+    Diagnostic::error()
+        .with_code("E0018")
+        .with_message("break outside of a loop")
+        .with_labels(vec![
+            Label::primary(file_id, span).with_message("cannot break outside of a while loop")
+        ])
+}
+
+fn continue_outside_loop(file_id: usize, span: Range<usize>) -> Diagnostic<usize> {
+    Diagnostic::error()
+        .with_code("E0019")
+        .with_message("continue outside of a loop")
+        .with_labels(vec![
+            Label::primary(file_id, span).with_message("cannot continue outside of a while loop")
+        ])
+}
+
+pub fn show_unused_variable_warnings<Name, Source>(unused: &[parser::Label], file_id: usize, files: &SimpleFiles<Name, Source>)
+    where Name: std::fmt::Display + Clone,
+          Source: AsRef<str> {
+    let diagnostics = unused.iter().map(|label| {
+        Diagnostic::warning()
+            .with_message(format!("unused variable '{}'", label.label))
+            .with_labels(vec![
+                Label::primary(file_id, label.span.clone()).with_message("never read")
+            ])
+            .with_notes(vec![format!("prefix it with an underscore to silence this warning: _{}", label.label)])
+    }).collect::<Vec<_>>();
+
+    if !diagnostics.is_empty() {
+        emit(&diagnostics, file_id, files);
+    }
+}
+
+/// How diagnostics are written to the standard error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorFormat {
+    /// Colored source snippets, for humans
+    #[default]
+    Human,
+    /// A JSON array of `{code, message, file, span: {start, end}, severity}` objects, for editors
+    /// and other tools. `span` is the byte range in `file` of the primary label, or `null` when
+    /// there is none. The diagnostics are collected and written as a single array by
+    /// `flush_diagnostics`.
+    Json,
+}
+
+thread_local! {
+    static ERROR_FORMAT: Cell<ErrorFormat> = const { Cell::new(ErrorFormat::Human) };
+    /// Diagnostics serialized to JSON objects, waiting for `flush_diagnostics`
+    static JSON_DIAGNOSTICS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn set_error_format(format: ErrorFormat) {
+    ERROR_FORMAT.with(|f| f.set(format));
+}
+
+/// Writes the diagnostics collected in JSON mode to the standard error, as a single array.
+/// Does nothing if there are none.
+pub fn flush_diagnostics() {
+    if let Some(json) = take_json_diagnostics() {
+        eprintln!("{}", json);
+    }
+}
+
+fn take_json_diagnostics() -> Option<String> {
+    let objects = JSON_DIAGNOSTICS.with(RefCell::take);
+    match objects.is_empty() {
+        true => None,
+        false => Some(format!("[{}]", objects.join(","))),
+    }
+}
+
+fn emit<Name, Source>(diagnostics: &[Diagnostic<usize>], file_id: usize, files: &SimpleFiles<Name, Source>)
+    where Name: std::fmt::Display + Clone,
+          Source: AsRef<str> {
+    match ERROR_FORMAT.with(Cell::get) {
+        ErrorFormat::Human => {
+            let writer = StandardStream::stderr(ColorChoice::Always);
+            let config = codespan_reporting::term::Config::default();
+
+            for diagnostic in diagnostics {
+                let _ = term::emit(&mut writer.lock(), &config, files, diagnostic);
+            }
+        }
+        ErrorFormat::Json => {
+            let file = match files.get(file_id) {
+                Ok(file) => json_string(&file.name().to_string()),
+                Err(_) => "null".to_owned(),
+            };
+            JSON_DIAGNOSTICS.with(|pending| {
+                let mut pending = pending.borrow_mut();
+                pending.extend(diagnostics.iter().map(|diagnostic| diagnostic_to_json(diagnostic, &file)));
+            });
+        }
+    }
+}
+
+/// `file` is the name of the file as a JSON value.
+fn diagnostic_to_json(diagnostic: &Diagnostic<usize>, file: &str) -> String {
+    let code = match &diagnostic.code {
+        Some(code) => json_string(code),
+        None => "null".to_owned(),
+    };
+    let severity = match diagnostic.severity {
+        Severity::Bug => "bug",
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+        Severity::Help => "help",
+    };
+    let span = match diagnostic.labels.iter().find(|l| l.style == LabelStyle::Primary) {
+        Some(label) => format!("{{\"start\":{},\"end\":{}}}", label.range.start, label.range.end),
+        None => "null".to_owned(),
+    };
+    format!("{{\"code\":{},\"message\":{},\"file\":{},\"span\":{},\"severity\":\"{}\"}}",
+            code, json_string(&diagnostic.message), file, span, severity)
+}
+
+fn json_string(string: &str) -> String {
+    let mut output = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if c.is_control() => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::InterpreterError;
+    use crate::lexer::Lexer;
+    use crate::symbol::Symbol;
+
+    use super::*;
+
+    fn files(name: &str, source: &str) -> (usize, SimpleFiles<String, String>) {
+        let mut files = SimpleFiles::new();
+        let file_id = files.add(name.to_owned(), source.to_owned());
+        (file_id, files)
+    }
+
+    #[test]
+    fn lexer_error_as_json() {
+        set_error_format(ErrorFormat::Json);
+        let source = "(+ 1 “2”)";
+        let (file_id, files) = files("script.rsp", source);
+        show_lexer_error(Lexer::new(source).tokenize().unwrap_err(), file_id, files);
+
+        assert_eq!(take_json_diagnostics().unwrap(), concat!(
+            r#"[{"code":"E0001","message":"unexpected character '“' (U+201C)","file":"script.rsp","#,
+            r#""span":{"start":5,"end":8},"severity":"error"}]"#,
+        ));
+        assert_eq!(take_json_diagnostics(), None);
+    }
+
+    #[test]
+    fn diagnostics_of_a_run_form_a_single_array() {
+        set_error_format(ErrorFormat::Json);
+        let (file_id, files) = files("main.rsp", "let x = 1\nimport \"lib.rsp\"");
+        let unused = parser::Label { label: Symbol::intern("x"), span: 4..5 };
+        show_unused_variable_warnings(&[unused], file_id, &files);
+
+        let error = InterpreterError::ImportFailed {
+            path: "lib.rsp".to_owned(),
+            source: "\"a\\tb\" (+ 1 y)".to_owned(),
+            error: Box::new(RunError::InterpreterError(InterpreterError::VariableNotFound("y".to_owned()).with_span(12..13))),
+        };
+        show_interpreter_error(error.with_span(10..26), file_id, files);
+
+        assert_eq!(take_json_diagnostics().unwrap(), concat!(
+            r#"[{"code":null,"message":"unused variable 'x'","file":"main.rsp","span":{"start":4,"end":5},"severity":"warning"},"#,
+            r#"{"code":"E0003","message":"variable 'y' not found","file":"lib.rsp","span":{"start":12,"end":13},"severity":"error"},"#,
+            r#"{"code":"E0023","message":"cannot import 'lib.rsp'","file":"main.rsp","span":{"start":10,"end":26},"severity":"error"}]"#,
+        ));
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("say \"hi\"\n\\\t\u{1}"), r#""say \"hi\"\n\\\t\u0001""#);
+    }
+
+    #[test]
+    fn smart_quotes_suggest_the_ascii_quote() {
+        let source = "(println “hi”)";
+        let (file_id, files) = files("script.rsp", source);
+        let error = Lexer::new(source).tokenize().unwrap_err();
+        assert!(matches!(&error, LexerError::UnexpectedCharacter(span) if &source[span.clone()] == "“"), "{:?}", error);
+
+        let diagnostic = lexer_diagnostic(error, file_id, &files);
+        assert_eq!(diagnostic.message, "unexpected character '“' (U+201C)");
+        let suggestions: Vec<&str> = diagnostic.labels.iter()
+            .filter(|label| label.style == LabelStyle::Secondary)
+            .map(|label| label.message.as_str())
+            .collect();
+        assert_eq!(suggestions, ["did you mean '\"'?"]);
+    }
+}
@@ -0,0 +1,202 @@
+/// Renders the syntax tree back to rusp source code.
+/// Scopes put each expression on its own line, indented by 4 spaces.
+/// Formatting the output of the parser gives back code that parses to the same syntax tree.
+use crate::parser::{Expression, ExpressionWithMetadata, Label, Pattern, UnaryOperator, Value};
+
+const INDENT: &str = "    ";
+
+pub fn format(expressions: &[ExpressionWithMetadata]) -> String {
+    let mut output = String::new();
+    for expression in expressions {
+        format_expression(&expression.expression, 0, &mut output);
+        output.push('\n');
+    }
+    output
+}
+
+fn format_expression(expression: &Expression, indent: usize, output: &mut String) {
+    match expression {
+        Expression::Id(id) => output.push_str(id.as_str()),
+        Expression::Value(value) => format_value(value, output),
+        Expression::Declaration(name, rhs) => {
+            output.push_str(&format!("let {} = ", name.label));
+            format_expression(&rhs.expression, indent, output);
+        }
+        Expression::Assignment(name, rhs) => {
+            output.push_str(&format!("{} = ", name.label));
+            format_expression(&rhs.expression, indent, output);
+        }
+        Expression::Scope(expressions) => {
+            if expressions.is_empty() {
+                output.push_str("{}");
+                return;
+            }
+            output.push_str("{\n");
+            for expression in expressions {
+                output.push_str(&INDENT.repeat(indent + 1));
+                format_expression(&expression.expression, indent + 1, output);
+                output.push('\n');
+            }
+            output.push_str(&INDENT.repeat(indent));
+            output.push('}');
+        }
+        Expression::List(elements) => {
+            output.push('[');
+            format_separated(elements, indent, output);
+            output.push(']');
+        }
+        Expression::NamedFunctionDefinition { name, parameters, body } => {
+            output.push_str(&format!("fn {} ", name.label));
+            format_parameters(parameters, output);
+            output.push(' ');
+            format_expression(&body.expression, indent, output);
+        }
+        Expression::AnonymousFunctionDefinition { parameters, body } => {
+            output.push_str("fn ");
+            format_parameters(parameters, output);
+            output.push(' ');
+            format_expression(&body.expression, indent, output);
+        }
+        Expression::FunctionCall(function_ptr, arguments) => {
+            output.push('(');
+            format_expression(&function_ptr.expression, indent, output);
+            if !arguments.is_empty() {
+                output.push(' ');
+                format_separated(arguments, indent, output);
+            }
+            output.push(')');
+        }
+        Expression::If { guard, base_case } => {
+            output.push_str("if ");
+            format_expression(&guard.expression, indent, output);
+            output.push(' ');
+            format_expression(&base_case.expression, indent, output);
+        }
+        Expression::IfElse { guard, base_case, else_case } => {
+            output.push_str("if ");
+            format_expression(&guard.expression, indent, output);
+            output.push(' ');
+            format_expression(&base_case.expression, indent, output);
+            output.push_str(" else ");
+            format_expression(&else_case.expression, indent, output);
+        }
+        Expression::While { guard, body } => {
+            output.push_str("while ");
+            format_expression(&guard.expression, indent, output);
+            output.push(' ');
+            format_expression(&body.expression, indent, output);
+        }
+        Expression::DoWhile { body, guard } => {
+            output.push_str("do ");
+            format_expression(&body.expression, indent, output);
+            output.push_str(" while ");
+            format_expression(&guard.expression, indent, output);
+        }
+        Expression::UnaryOp(UnaryOperator::Negate, operand) => {
+            output.push_str("(- ");
+            format_expression(&operand.expression, indent, output);
+            output.push(')');
+        }
+        Expression::UnaryOp(UnaryOperator::Not, operand) => {
+            output.push('!');
+            format_expression(&operand.expression, indent, output);
+        }
+        Expression::Import(path) => output.push_str(&format!("import \"{}\"", path)),
+        Expression::Break => output.push_str("break"),
+        Expression::Continue => output.push_str("continue"),
+        Expression::Match { scrutinee, arms } => {
+            output.push_str("match ");
+            format_expression(&scrutinee.expression, indent, output);
+            if arms.is_empty() {
+                output.push_str(" {}");
+                return;
+            }
+            output.push_str(" {\n");
+            for arm in arms {
+                output.push_str(&INDENT.repeat(indent + 1));
+                match &arm.pattern {
+                    Pattern::Value(value) => format_value(value, output),
+                    Pattern::Wildcard => output.push('_'),
+                }
+                output.push_str(" => ");
+                format_expression(&arm.body.expression, indent + 1, output);
+                output.push('\n');
+            }
+            output.push_str(&INDENT.repeat(indent));
+            output.push('}');
+        }
+    }
+}
+
+fn format_separated(expressions: &[ExpressionWithMetadata], indent: usize, output: &mut String) {
+    for (i, expression) in expressions.iter().enumerate() {
+        if i > 0 {
+            output.push(' ');
+        }
+        format_expression(&expression.expression, indent, output);
+    }
+}
+
+fn format_parameters(parameters: &[Label], output: &mut String) {
+    let parameters = parameters.iter().map(|p| p.label.as_str()).collect::<Vec<_>>();
+    output.push_str(&format!("({})", parameters.join(" ")));
+}
+
+fn format_value(value: &Value, output: &mut String) {
+    match value {
+        Value::Float(float) => {
+            // Keep the decimal point so the literal is read back as a float
+            let float = float.to_string();
+            output.push_str(&float);
+            if !float.contains('.') {
+                output.push_str(".0");
+            }
+        }
+        Value::Long(long) => output.push_str(&format!("{}L", long)),
+        // The lexer keeps escape sequences as they are in the string
+        Value::String(string) => output.push_str(&format!("\"{}\"", string)),
+        value => output.push_str(&value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run, Lexer, Parser};
+
+    fn format_source(source: &str) -> String {
+        let (tokens, indices) = Lexer::new(source).tokenize().unwrap();
+        format(&Parser::new((tokens.as_slice(), indices.as_slice())).parse().unwrap())
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let source = r#"
+            fn collatz (n) { let steps = 0 while (!= n 1) { if (== (% n 2) 0) { n = (/ n 2) } else n = (+ (* 3 n) 1) steps = (+ steps 1) } steps }
+            fn sign (x) if (< x 0) -1 else if (== x 0) 0 else 1
+            let l = [1 2.0 3L "a" true ()]
+            do { (pop l) } while (> (len l) 3)
+            (+ (collatz 27) (sign -5) (if true 1))
+        "#;
+        let formatted = format_source(source);
+        assert_eq!(formatted, r#"fn collatz (n) {
+    let steps = 0
+    while (!= n 1) {
+        if (== (% n 2) 0) {
+            n = (/ n 2)
+        } else n = (+ (* 3 n) 1)
+        steps = (+ steps 1)
+    }
+    steps
+}
+fn sign (x) if (< x 0) -1 else if (== x 0) 0 else 1
+let l = [1 2.0 3L "a" true ()]
+do {
+    (pop l)
+} while (> (len l) 3)
+(+ (collatz 27) (sign -5) if true 1)
+"#);
+        assert_eq!(format_source(&formatted), formatted);
+        assert_eq!(run(&formatted).unwrap().to_string(), run(source).unwrap().to_string());
+    }
+}
@@ -1,16 +1,24 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::fs;
 use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use crate::interpreter::InterpreterError::*;
-use crate::parser::{Context, Expression, ExpressionWithMetadata, Function, Value};
+use crate::lexer::Lexer;
+use crate::native_functions::{negate, values_equal};
+use crate::parser::{Context, Expression, ExpressionWithMetadata, Function, Parser, Pattern, UnaryOperator, Value};
+use crate::RunError;
+use crate::symbol::Symbol;
 
 #[derive(Debug)]
 pub struct InterpreterErrorWithSpan {
     pub error: InterpreterError,
     pub span: Option<Range<usize>>,
+    /// Set by native functions to blame one of their arguments, whose span is used by the caller.
+    pub argument: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -20,6 +28,25 @@ pub enum InterpreterError {
     WrongNumberOfArguments,
     InvalidOperands,
     StdInError,
+    DivisionByZero,
+    RecursionLimitExceeded,
+    IndexOutOfBounds,
+    InvalidIndex,
+    KeyNotFound,
+    AssertionFailed(String),
+    ParseError(String),
+    IoError(String),
+    /// The result of an integer operation doesn't fit in an integer, with the name of the operation
+    IntegerOverflow(String),
+    /// The imported file is already being imported, with its path
+    ImportCycle(String),
+    /// The imported file couldn't be lexed, parsed or evaluated, `error` refers to `source`
+    ImportFailed { path: String, source: String, error: Box<RunError> },
+    /// Not an error: raised by `exit` to unwind the interpreter, carrying the status code.
+    Exit(i32),
+    /// Not errors either: raised by `break` and `continue`, caught by the enclosing loop.
+    Break,
+    Continue,
 }
 
 impl InterpreterError {
@@ -27,6 +54,15 @@ impl InterpreterError {
         InterpreterErrorWithSpan {
             error: self,
             span: Some(span),
+            argument: None,
+        }
+    }
+
+    pub fn at_argument(self, index: usize) -> InterpreterErrorWithSpan {
+        InterpreterErrorWithSpan {
+            error: self,
+            span: None,
+            argument: Some(index),
         }
     }
 }
@@ -36,34 +72,238 @@ impl From<InterpreterError> for InterpreterErrorWithSpan {
         InterpreterErrorWithSpan {
             error,
             span: None,
+            argument: None,
+        }
+    }
+}
+
+/// Maximum number of nested rusp function calls. Every call costs several Rust stack frames,
+/// so without a bound a runaway recursion would overflow the stack and abort the process.
+pub const DEFAULT_RECURSION_LIMIT: usize = 1000;
+
+/// Stack reserved for each nested rusp function call. A call to a small function takes about
+/// 40 KiB in a debug build, the rest leaves room for nested expressions in the function body.
+pub const STACK_SIZE_PER_CALL: usize = 256 * 1024;
+
+/// Stack size of a thread that can evaluate `DEFAULT_RECURSION_LIMIT` nested calls.
+/// The main thread's stack is usually too small, evaluate on a thread spawned with this size.
+pub const DEFAULT_STACK_SIZE: usize = DEFAULT_RECURSION_LIMIT * STACK_SIZE_PER_CALL;
+
+thread_local! {
+    static RECURSION_LIMIT: Cell<usize> = const { Cell::new(DEFAULT_RECURSION_LIMIT) };
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The thread evaluating rusp code needs a stack of at least `limit * STACK_SIZE_PER_CALL` bytes,
+/// `run` spawns one for the limit of the calling thread.
+pub fn set_recursion_limit(limit: usize) {
+    RECURSION_LIMIT.with(|l| l.set(limit));
+}
+
+thread_local! {
+    static FLOAT_PRECISION: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Number of decimals used to print floats. By default floats are printed with as many
+/// decimals as needed to read back the same number, `0.1` is printed `0.1`.
+pub fn set_float_precision(precision: Option<usize>) {
+    FLOAT_PRECISION.with(|p| p.set(precision));
+}
+
+thread_local! {
+    /// Canonical paths of the files being evaluated, the innermost import last
+    static IMPORT_STACK: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Makes the imports of the script relative to its directory, instead of the working directory.
+pub fn set_script_path(path: &Path) {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    IMPORT_STACK.with(|s| *s.borrow_mut() = vec![path]);
+}
+
+/// The settings of the current thread, to evaluate on another thread as if on this one.
+pub(crate) struct Settings {
+    recursion_limit: usize,
+    float_precision: Option<usize>,
+    import_stack: Vec<PathBuf>,
+}
+
+impl Settings {
+    pub(crate) fn current() -> Self {
+        Settings {
+            recursion_limit: RECURSION_LIMIT.with(|l| l.get()),
+            float_precision: FLOAT_PRECISION.with(|p| p.get()),
+            import_stack: IMPORT_STACK.with(|s| s.borrow().clone()),
+        }
+    }
+
+    /// Stack size of a thread that can evaluate `recursion_limit` nested calls.
+    pub(crate) fn stack_size(&self) -> usize {
+        self.recursion_limit.saturating_mul(STACK_SIZE_PER_CALL).max(DEFAULT_STACK_SIZE)
+    }
+
+    pub(crate) fn apply(self) {
+        set_recursion_limit(self.recursion_limit);
+        set_float_precision(self.float_precision);
+        IMPORT_STACK.with(|s| *s.borrow_mut() = self.import_stack);
+    }
+}
+
+/// Keeps track of the files being imported, removing the innermost one when dropped.
+struct ImportGuard;
+
+impl ImportGuard {
+    fn enter(path: PathBuf) -> Result<Self, InterpreterError> {
+        IMPORT_STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if stack.contains(&path) {
+                return Err(ImportCycle(path.display().to_string()));
+            }
+            stack.push(path);
+            Ok(ImportGuard)
+        })
+    }
+}
+
+impl Drop for ImportGuard {
+    fn drop(&mut self) {
+        IMPORT_STACK.with(|s| s.borrow_mut().pop());
+    }
+}
+
+/// Evaluates the file in the global context, so that what it declares is available everywhere.
+fn import(path: &str, context: Rc<RefCell<Context>>) -> Result<Value, InterpreterError> {
+    let importing_directory = IMPORT_STACK.with(|s| s.borrow().last().and_then(|p| p.parent().map(Path::to_owned)));
+    let path = match importing_directory {
+        Some(directory) => directory.join(path),
+        None => PathBuf::from(path),
+    };
+    let path = path.canonicalize()
+        .map_err(|err| IoError(format!("cannot import '{}': {}", path.display(), err)))?;
+    let _guard = ImportGuard::enter(path.clone())?;
+    let source = fs::read_to_string(&path)
+        .map_err(|err| IoError(format!("cannot import '{}': {}", path.display(), err)))?;
+
+    let global_context = global_context(context);
+    let evaluate = || -> Result<(), RunError> {
+        let (tokens, indices) = Lexer::new(&source).tokenize()?;
+        let expressions = Parser::new((tokens.as_slice(), indices.as_slice())).parse()?;
+        for expression in &expressions {
+            expression.evaluate(global_context.clone())?;
+        }
+        Ok(())
+    };
+    match evaluate() {
+        Ok(()) => Ok(Value::Unit),
+        // Exiting from an imported file still ends the program
+        Err(RunError::InterpreterError(InterpreterErrorWithSpan { error: Exit(code), .. })) => Err(Exit(code)),
+        Err(error) => Err(ImportFailed {
+            path: path.display().to_string(),
+            source,
+            error: Box::new(error),
+        }),
+    }
+}
+
+fn global_context(mut context: Rc<RefCell<Context>>) -> Rc<RefCell<Context>> {
+    loop {
+        let parent = context.borrow().parent_context.clone();
+        match parent {
+            Some(parent) => context = parent,
+            None => return context,
         }
     }
 }
 
+/// Keeps track of the current call depth, decrementing it when dropped.
+struct CallDepthGuard;
+
+impl CallDepthGuard {
+    fn enter() -> Result<Self, InterpreterError> {
+        let depth = CALL_DEPTH.with(|d| d.get());
+        if depth >= RECURSION_LIMIT.with(|l| l.get()) {
+            return Err(RecursionLimitExceeded);
+        }
+        CALL_DEPTH.with(|d| d.set(depth + 1));
+        Ok(CallDepthGuard)
+    }
+}
+
+impl Drop for CallDepthGuard {
+    fn drop(&mut self) {
+        CALL_DEPTH.with(|d| d.set(d.get() - 1));
+    }
+}
+
+/// Variable lookup through the chain of contexts. A variable in a context shadows the variables
+/// with the same name in its parents.
 pub trait ContextTrait {
-    fn get_variable(&self, name: &str) -> Option<Value>;
-    fn set_variable(&self, name: &str, value: Value) -> Result<(), ()>;
+    /// Returns a copy of the value of the nearest variable with this name, searching this context
+    /// first and then its parents. Lists, maps and functions are shared, not deep copied.
+    fn get_variable(&self, name: Symbol) -> Option<Value>;
+    /// Assigns to the nearest existing variable with this name.
+    /// Fails with `VariableNotFound` if no context in the chain declares it, assignment never
+    /// creates a variable.
+    fn set_variable(&self, name: Symbol, value: Value) -> Result<(), InterpreterError>;
+    /// Creates a variable in this context, shadowing any variable with the same name in the
+    /// parents and replacing one previously declared in this context.
+    fn declare_variable(&self, name: Symbol, value: Value);
 }
 
 impl ContextTrait for Rc<RefCell<Context>> {
-    fn get_variable(&self, name: &str) -> Option<Value> {
+    fn get_variable(&self, name: Symbol) -> Option<Value> {
         let b = RefCell::borrow(self);
-        match b.variables.get(name) {
+        match b.variables.get(&name) {
             None => b.parent_context.as_ref().and_then(|p| p.get_variable(name)),
             Some(value) => Some(value.clone())
         }
     }
 
-    fn set_variable(&self, name: &str, new_value: Value) -> Result<(), ()> {
+    fn set_variable(&self, name: Symbol, new_value: Value) -> Result<(), InterpreterError> {
         let mut b = RefCell::borrow_mut(self);
-        match b.variables.get_mut(name) {
-            None => b.parent_context.as_ref().ok_or(()).and_then(|p| p.set_variable(name, new_value)),
+        match b.variables.get_mut(&name) {
+            None => match &b.parent_context {
+                Some(parent) => parent.set_variable(name, new_value),
+                None => Err(VariableNotFound(name.to_string())),
+            },
             Some(value) => {
                 *value = new_value;
                 Ok(())
             }
         }
     }
+
+    fn declare_variable(&self, name: Symbol, value: Value) {
+        self.borrow_mut().variables.insert(name, value);
+    }
+}
+
+thread_local! {
+    /// Addresses of the lists and maps being printed, the outermost first
+    static PRINTING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Keeps track of a list or map being printed, removing it when dropped.
+/// A list or map containing itself is printed `[...]` or `{...}` the second time.
+struct PrintGuard;
+
+impl PrintGuard {
+    fn enter(address: *const ()) -> Option<Self> {
+        PRINTING.with(|printing| {
+            let mut printing = printing.borrow_mut();
+            if printing.contains(&address) {
+                return None;
+            }
+            printing.push(address);
+            Some(PrintGuard)
+        })
+    }
+}
+
+impl Drop for PrintGuard {
+    fn drop(&mut self) {
+        PRINTING.with(|p| p.borrow_mut().pop());
+    }
 }
 
 impl Display for Value {
@@ -71,9 +311,41 @@ impl Display for Value {
         match self {
             Value::Unit => write!(f, "()"),
             Value::Integer(int) => write!(f, "{}", int),
-            Value::Float(float) => write!(f, "{}", float),
+            Value::Long(long) => write!(f, "{}", long),
+            Value::Float(float) => match FLOAT_PRECISION.with(Cell::get) {
+                Some(precision) => write!(f, "{:.*}", precision, float),
+                None => write!(f, "{}", float),
+            },
             Value::String(string) => write!(f, "{}", string),
             Value::Boolean(b) => write!(f, "{}", if *b { "true" } else { "false" }),
+            Value::List(list) => {
+                let _guard = match PrintGuard::enter(Rc::as_ptr(list) as *const ()) {
+                    Some(guard) => guard,
+                    None => return write!(f, "[...]"),
+                };
+                write!(f, "[")?;
+                for (i, element) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                let _guard = match PrintGuard::enter(Rc::as_ptr(map) as *const ()) {
+                    Some(guard) => guard,
+                    None => return write!(f, "{{...}}"),
+                };
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
             Value::Function(Function::NativeFunction { name, .. }) => write!(f, "fn {}", name),
             Value::Function(Function::RuspFunction { name, .. }) => write!(f, "fn {}", name),
         }
@@ -81,36 +353,44 @@ impl Display for Value {
 }
 
 impl ExpressionWithMetadata {
-    pub(crate) fn evaluate(&self, context: Rc<RefCell<Context>>) -> Result<Value, InterpreterErrorWithSpan> {
+    pub fn evaluate(&self, context: Rc<RefCell<Context>>) -> Result<Value, InterpreterErrorWithSpan> {
         match &self.expression {
-            Expression::Id(id) => context.get_variable(id as &str)
-                .ok_or(VariableNotFound(id.to_owned()).with_span(self.span.clone())),
+            Expression::Id(id) => context.get_variable(*id)
+                .ok_or_else(|| VariableNotFound(id.to_string()).with_span(self.span.clone())),
             Expression::Value(value) => Ok(value.clone()),
             Expression::Declaration(name, rhs) => {
                 let rhs = rhs.evaluate(context.clone())?;
-                context.borrow_mut().variables.insert(name.label.clone(), rhs);
+                context.declare_variable(name.label, rhs);
                 Ok(Value::Unit)
             }
             Expression::Assignment(name, rhs) => {
                 let rhs = rhs.evaluate(context.clone())?;
-                match context.set_variable(&name.label, rhs) {
-                    Ok(()) => Ok(Value::Unit),
-                    Err(()) => Err(VariableNotFound(name.label.to_owned())
-                        .with_span(name.span.clone()))
-                }
+                context.set_variable(name.label, rhs)
+                    .map(|()| Value::Unit)
+                    .map_err(|e| e.with_span(name.span.clone()))
             }
             Expression::Scope(expressions) => {
                 let context = Rc::new(RefCell::new(Context::with_parent(context.clone())));
 
-                expressions.iter().fold(Ok(Value::Unit), |acc, expression| {
-                    acc.and(expression.evaluate(context.clone()))
-                })
+                // Stop at the first error, break or continue
+                let mut value = Value::Unit;
+                for expression in expressions {
+                    value = expression.evaluate(context.clone())?;
+                }
+                Ok(value)
+            }
+            Expression::List(elements) => {
+                let mut values = vec![];
+                for element in elements {
+                    values.push(element.evaluate(context.clone())?);
+                }
+                Ok(Value::List(Rc::new(RefCell::new(values))))
             }
             Expression::NamedFunctionDefinition { name, parameters, body } => {
-                context.borrow_mut().variables.insert(name.label.clone(), Value::Function(Function::RuspFunction {
+                context.declare_variable(name.label, Value::Function(Function::RuspFunction {
                     closing_context: context.clone(),
-                    name: name.label.clone(),
-                    parameters: parameters.iter().map(|p| p.label.clone()).collect(),
+                    name: name.label.to_string(),
+                    parameters: parameters.iter().map(|p| p.label).collect(),
                     body: body.clone(),
                 }));
                 Ok(Value::Unit)
@@ -119,7 +399,7 @@ impl ExpressionWithMetadata {
                 Ok(Value::Function(Function::RuspFunction {
                     closing_context: context.clone(),
                     name: "*anonymous*".to_owned(),
-                    parameters: parameters.iter().map(|p| p.label.clone()).collect(),
+                    parameters: parameters.iter().map(|p| p.label).collect(),
                     body: body.clone(),
                 }))
             }
@@ -130,7 +410,8 @@ impl ExpressionWithMetadata {
                 }
                 match function_ptr.evaluate(context)? {
                     Value::Function(f) => {
-                        f.call(values).map_err(|mut err| {
+                        let spans = arguments.iter().map(|arg| arg.span.clone()).collect::<Vec<_>>();
+                        f.call_with_argument_spans(values, &spans).map_err(|mut err| {
                             if err.span.is_none() {
                                 err.span = Some(self.span.clone());
                             }
@@ -147,10 +428,11 @@ impl ExpressionWithMetadata {
                     Value::Boolean(b) => b,
                     _ => false, // We don't do implicit casting to boolean
                 };
-                if is_guard_true {
-                    base_case.evaluate(context)?;
+                match is_guard_true {
+                    true => base_case.evaluate(context),
+                    // There's no else case to take its value from
+                    false => Ok(Value::Unit),
                 }
-                Ok(Value::Unit)
             }
             Expression::IfElse { guard, base_case, else_case } => {
                 let context = Rc::new(RefCell::new(Context::with_parent(context)));
@@ -173,24 +455,83 @@ impl ExpressionWithMetadata {
                         _ => false, // We don't do implicit casting to boolean
                     }
                 } {
-                    body.evaluate(context.clone())?;
+                    match body.evaluate(context.clone()) {
+                        Err(InterpreterErrorWithSpan { error: InterpreterError::Break, .. }) => break,
+                        Err(InterpreterErrorWithSpan { error: InterpreterError::Continue, .. }) => continue,
+                        result => result?,
+                    };
+                }
+                Ok(Value::Unit)
+            }
+            Expression::DoWhile { body, guard } => {
+                let context = Rc::new(RefCell::new(Context::with_parent(context)));
+
+                loop {
+                    match body.evaluate(context.clone()) {
+                        Err(InterpreterErrorWithSpan { error: InterpreterError::Break, .. }) => break,
+                        // Continue still checks the guard
+                        Err(InterpreterErrorWithSpan { error: InterpreterError::Continue, .. }) => (),
+                        result => { result?; }
+                    }
+                    match guard.evaluate(context.clone())? {
+                        Value::Boolean(true) => (),
+                        _ => break, // We don't do implicit casting to boolean
+                    }
                 }
                 Ok(Value::Unit)
             }
+            Expression::Match { scrutinee, arms } => {
+                let context = Rc::new(RefCell::new(Context::with_parent(context)));
+
+                let value = scrutinee.evaluate(context.clone())?;
+                let arm = arms.iter().find(|arm| match &arm.pattern {
+                    Pattern::Value(pattern) => values_equal(&value, pattern),
+                    Pattern::Wildcard => true,
+                });
+                match arm {
+                    Some(arm) => arm.body.evaluate(context),
+                    // No arm taken
+                    None => Ok(Value::Unit),
+                }
+            }
+            Expression::UnaryOp(operator, operand) => {
+                let operand = operand.evaluate(context)?;
+                let result = match (operator, operand) {
+                    (UnaryOperator::Negate, operand) => negate(&operand),
+                    (UnaryOperator::Not, Value::Boolean(b)) => Ok(Value::Boolean(!b)),
+                    (UnaryOperator::Not, _) => Err(InvalidOperands),
+                };
+                result.map_err(|e| e.with_span(self.span.clone()))
+            }
+            Expression::Import(path) => import(path, context).map_err(|e| e.with_span(self.span.clone())),
+            Expression::Break => Err(InterpreterError::Break.with_span(self.span.clone())),
+            Expression::Continue => Err(InterpreterError::Continue.with_span(self.span.clone())),
         }
     }
 }
 
 impl Function {
     pub fn call(&self, args: Vec<Value>) -> Result<Value, InterpreterErrorWithSpan> {
+        self.call_with_argument_spans(args, &[])
+    }
+
+    /// Errors blaming an argument get the span of that argument, if it's known.
+    pub fn call_with_argument_spans(&self, args: Vec<Value>, spans: &[Range<usize>]) -> Result<Value, InterpreterErrorWithSpan> {
         match self {
             Function::NativeFunction { closing_context, name: _, fn_pointer } => {
-                fn_pointer(closing_context.clone(), args)
+                fn_pointer(closing_context.clone(), args).map_err(|mut err| {
+                    // The argument index only makes sense to the caller of this function
+                    if let Some(index) = err.argument.take() {
+                        err.span = err.span.or_else(|| spans.get(index).cloned());
+                    }
+                    err
+                })
             }
             Function::RuspFunction { closing_context, name: _, parameters, body } => {
                 if parameters.len() != args.len() {
                     return Err(InterpreterError::WrongNumberOfArguments.into());
                 }
+                let _guard = CallDepthGuard::enter()?;
 
                 // Put the arguments in the context
                 let context = Rc::new(RefCell::new(Context {
@@ -198,13 +539,195 @@ impl Function {
                     variables: {
                         let mut hashmap = HashMap::new();
                         for (param, arg) in parameters.iter().zip(args) {
-                            hashmap.insert(param.to_owned(), arg);
+                            hashmap.insert(*param, arg);
                         }
                         hashmap
                     },
+                    ..Default::default()
                 }));
                 body.evaluate(context)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::{run, RunError};
+
+    use super::*;
+
+    /// Runs `source` on a thread with the stack the interpreter expects, keeping only the error.
+    fn run_error(source: &'static str) -> Option<InterpreterError> {
+        thread::Builder::new()
+            .stack_size(DEFAULT_STACK_SIZE)
+            .spawn(move || match run(source) {
+                Err(RunError::InterpreterError(err)) => Some(err.error),
+                _ => None,
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn unbounded_recursion_exceeds_the_limit() {
+        let error = run_error("fn f (n) (+ 1 (f n)) (f 0)");
+        assert!(matches!(error, Some(RecursionLimitExceeded)), "{:?}", error);
+    }
+
+    #[test]
+    fn recursion_below_the_limit_succeeds() {
+        let error = run_error("fn f (n) if (== n 0) 0 else (+ 1 (f (- n 1))) (f 900)");
+        assert!(error.is_none(), "{:?}", error);
+    }
+
+    fn eval(source: &str) -> Value {
+        run(source).unwrap()
+    }
+
+    #[test]
+    fn match_takes_the_first_equal_arm() {
+        let source = r#"
+            fn describe (x) match x {
+                0 => "zero"
+                1 => "one"
+                1 => "unreachable"
+                "one" => "string"
+                _ => "many"
+            }
+            [(describe 0) (describe 1.0) (describe "one") (describe 5)]
+        "#;
+        assert_eq!(eval(source).to_string(), "[zero one string many]");
+        // No arm taken
+        assert!(matches!(eval("match 3 { 1 => 1 }"), Value::Unit));
+    }
+
+    #[test]
+    fn break_exits_the_loop_early() {
+        let source = "
+            let i = 0
+            while true {
+                if (== i 3) { break }
+                i = (+ i 1)
+            }
+            i
+        ";
+        assert!(matches!(eval(source), Value::Integer(3)));
+    }
+
+    #[test]
+    fn continue_skips_an_iteration() {
+        let source = "
+            let i = 0
+            let skipped = []
+            let kept = []
+            do {
+                i = (+ i 1)
+                if (== (% i 2) 0) {
+                    (push skipped i)
+                    continue
+                }
+                (push kept i)
+            } while (< i 5)
+            [kept skipped]
+        ";
+        assert_eq!(eval(source).to_string(), "[[1 3 5] [2 4]]");
+    }
+
+    #[test]
+    fn unary_operators() {
+        assert!(matches!(eval("(- 5)"), Value::Integer(-5)));
+        assert!(matches!(eval("let x = 2.5 (- x)"), Value::Float(x) if x == -2.5));
+        assert!(matches!(eval("!true"), Value::Boolean(false)));
+        assert!(matches!(eval("let t = false !t"), Value::Boolean(true)));
+        assert!(matches!(run("!1"), Err(RunError::InterpreterError(e)) if matches!(e.error, InvalidOperands)));
+        assert!(matches!(run("(- -2147483648)"), Err(RunError::InterpreterError(e)) if matches!(e.error, IntegerOverflow(_))));
+    }
+
+    fn error_span(source: &str) -> Option<Range<usize>> {
+        match run(source) {
+            Err(RunError::InterpreterError(err)) => err.span,
+            result => panic!("expected an interpreter error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn type_errors_point_at_the_argument() {
+        let source = r#"(+ 1 true 2)"#;
+        assert_eq!(error_span(source).map(|span| &source[span]), Some("true"));
+        let source = r#"(* 2 (+ 1 1) "x")"#;
+        assert_eq!(error_span(source).map(|span| &source[span]), Some(r#""x""#));
+        // Errors that aren't about one argument point at the whole call
+        let source = "(/ 1 0)";
+        assert_eq!(error_span(source).map(|span| &source[span]), Some("(/ 1 0)"));
+    }
+
+    #[test]
+    fn if_without_else_evaluates_to_its_branch_or_unit() {
+        assert!(matches!(eval("if true { 2 }"), Value::Integer(2)));
+        assert!(matches!(eval("if false { 2 }"), Value::Unit));
+        assert!(matches!(eval("(+ 1 (if true { 2 }))"), Value::Integer(3)));
+        assert!(matches!(eval("(+ 1 (if false 2 else 3))"), Value::Integer(4)));
+        // The function chosen by a condition can still be called with arguments
+        assert!(matches!(eval("fn inc (x) (+ x 1) ((if true inc else 2) 1)"), Value::Integer(2)));
+    }
+
+    #[test]
+    fn do_while_runs_its_body_before_checking_the_guard() {
+        assert!(matches!(eval("let runs = 0 do { runs = (+ runs 1) } while false runs"), Value::Integer(1)));
+        assert!(matches!(eval("let runs = 0 do { runs = (+ runs 1) } while (< runs 3) runs"), Value::Integer(3)));
+        assert!(matches!(eval("let runs = 0 do { runs = (+ runs 1) if (== runs 2) { break } } while true runs"), Value::Integer(2)));
+    }
+
+    #[test]
+    fn closures_share_the_variables_they_capture() {
+        let source = "
+            fn make_counter () {
+                let count = 0
+                fn () { count = (+ count 1) count }
+            }
+            let counter = (make_counter)
+            let other = (make_counter)
+            (counter) (counter) (other)
+            [(counter) (other)]
+        ";
+        assert_eq!(eval(source).to_string(), "[3 2]");
+
+        // Assignments after the closure is created are visible from it
+        let source = "
+            let x = 1
+            let read_x = fn () x
+            x = 2
+            (read_x)
+        ";
+        assert!(matches!(eval(source), Value::Integer(2)));
+    }
+
+    #[test]
+    fn child_contexts_shadow_and_assign_through_their_parents() {
+        let (x, y) = (Symbol::intern("x"), Symbol::intern("y"));
+        let parent = Rc::new(RefCell::new(Context::default()));
+        parent.declare_variable(x, Value::Integer(1));
+        let child = Rc::new(RefCell::new(Context::with_parent(parent.clone())));
+
+        // Lookups fall back to the parent
+        assert!(matches!(child.get_variable(x), Some(Value::Integer(1))));
+        // Assignments change the parent's variable
+        child.set_variable(x, Value::Integer(2)).unwrap();
+        assert!(matches!(parent.get_variable(x), Some(Value::Integer(2))));
+
+        // A declaration in the child shadows the parent's variable, which keeps its value
+        child.declare_variable(x, Value::Integer(3));
+        child.set_variable(x, Value::Integer(4)).unwrap();
+        assert!(matches!(child.get_variable(x), Some(Value::Integer(4))));
+        assert!(matches!(parent.get_variable(x), Some(Value::Integer(2))));
+
+        // Assigning an undeclared variable doesn't declare it
+        assert!(matches!(child.set_variable(y, Value::Unit), Err(VariableNotFound(name)) if name == "y"));
+        assert!(child.get_variable(y).is_none());
+        assert!(parent.get_variable(y).is_none());
+    }
+}
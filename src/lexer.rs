@@ -3,17 +3,19 @@ use std::ops::Range;
 /// Architecture similar to this image:
 /// https://miro.medium.com/max/875/1%2aluy_LfooQ8dLjhOiaZ1mrg.png
 ///
-/// Initially, Lexer::view is the array of chars of the source code.
+/// Lexer::view() returns the next few chars of the source code as an array.
 /// I opted to use a char array instead of using iterators because I can fully exploit pattern
 /// matching to look ahead, instead of manually calling .peak() on an iterator.
-/// As the lexer reads the characters, it re-slices the view. The next character to be read will
-/// always be at index 0. It outputs a vector of Tokens to be used by the parser.
+/// As the lexer reads the characters, it re-slices the remaining source. The next character to be
+/// read will always be at index 0 of the view. It outputs a vector of Tokens to be used by the parser.
 #[derive(Debug, Clone)]
 pub enum Token {
     Id(String),
     Literal(Literal),
     Keyword(Keyword),
     Equal,
+    FatArrow,
+    ExclamationMark,
     LeftParenthesis,
     RightParenthesis,
     LeftSquareBracket,
@@ -23,9 +25,11 @@ pub enum Token {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Literal {
-    Float(f32),
+    Float(f64),
     Integer(i32),
+    Long(i64),
     String(String),
 }
 
@@ -39,35 +43,68 @@ pub enum Keyword {
     False,
     Fn,
     Let,
+    Match,
+    Break,
+    Continue,
+    Do,
+    Import,
 }
 
 #[derive(Debug)]
 pub enum LexerError {
     UnexpectedCharacter(Range<usize>),
+    /// An integer or long literal too large for its type
+    NumberOutOfRange(Range<usize>),
+}
+
+/// How many chars the lexer can look ahead.
+const VIEW_SIZE: usize = 2;
+
+#[derive(Clone, Copy)]
+struct View {
+    chars: [char; VIEW_SIZE],
+    len: usize,
+}
+
+impl View {
+    fn as_slice(&self) -> &[char] {
+        &self.chars[..self.len]
+    }
 }
 
 pub struct Lexer<'a> {
-    chars: &'a [char],
+    source: &'a str,
+    remaining: &'a str,
     utf8_index: usize,
     tokens: Vec<Token>,
     indices: Vec<Range<usize>>,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(chars: &'a [char]) -> Self {
+    pub fn new(source: &'a str) -> Self {
         Self {
-            chars,
+            source,
+            remaining: source,
             utf8_index: 0,
             tokens: vec![],
             indices: vec![],
         }
     }
 
+    fn view(&self) -> View {
+        let mut view = View { chars: ['\0'; VIEW_SIZE], len: 0 };
+        for c in self.remaining.chars().take(VIEW_SIZE) {
+            view.chars[view.len] = c;
+            view.len += 1;
+        }
+        view
+    }
+
     pub fn advance_by(&mut self, n: usize) {
-        for char in &self.chars[..n] {
+        for char in self.remaining.chars().take(n) {
             self.utf8_index += char.len_utf8();
         }
-        self.chars = &self.chars[n..];
+        self.remaining = &self.source[self.utf8_index..];
     }
 
     pub fn add_token(&mut self, token: Token, range: Range<usize>) {
@@ -77,14 +114,17 @@ impl<'a> Lexer<'a> {
 
     pub fn tokenize(mut self) -> Result<(Vec<Token>, Vec<Range<usize>>), LexerError> {
         loop {
-            match self.chars {
+            match self.view().as_slice() {
                 [w, ..] if w.is_whitespace() => self.advance_by(1),
                 ['/', '/', ..] => self.process_comments()?,
                 ['"', ..] => self.process_string_literals()?,
                 [digit, ..] if digit.is_ascii_digit() => self.process_numeric_literals()?,
                 ['+' | '-', digit, ..] if digit.is_ascii_digit() => self.process_numeric_literals()?,
                 // Special rules for the equal sign
-                // "=" alone is reserved but it can be used in identifiers
+                // "=" alone is reserved but it can be used in identifiers, "=>" is always an arrow
+                ['=', '>', ..] => self.process_operators_and_punctuation()?,
+                // "!x" is a negation, while "!" and "!=" alone are identifiers
+                ['!', c, ..] if *c != '=' && is_valid_identifier_character(*c) => self.process_operators_and_punctuation()?,
                 ['=', c, ..] if !is_valid_identifier_character(*c) => self.process_operators_and_punctuation()?,
                 ['=', c, ..] if is_valid_identifier_character(*c) => self.process_keywords_and_identifiers()?,
                 [p, ..] if is_punctuation(*p) => self.process_operators_and_punctuation()?,
@@ -97,57 +137,41 @@ impl<'a> Lexer<'a> {
     }
 
     fn process_keywords_and_identifiers(&mut self) -> Result<(), LexerError> {
+        use Keyword::*;
         let start_index = self.utf8_index;
-        let start = self.chars;
-        let mut i = 0;
-
-        let end_token = |i: usize| -> Option<Token> {
-            use Keyword::*;
-            match start.is_empty() {
-                true => None,
-                false => {
-                    let token = start[..i].iter().collect::<String>();
-                    let token = match token.as_str() {
-                        "if" => Token::Keyword(If),
-                        "else" => Token::Keyword(Else),
-                        "while" => Token::Keyword(While),
-                        "for" => Token::Keyword(For),
-                        "true" => Token::Keyword(True),
-                        "false" => Token::Keyword(False),
-                        "fn" => Token::Keyword(Fn),
-                        "let" => Token::Keyword(Let),
-                        _ => Token::Id(start[..i].iter().collect::<String>())
-                    };
-                    Some(token)
-                }
-            }
-        };
 
-        loop {
-            match self.chars {
-                [c, ..] if !is_valid_identifier_character(*c) => {
-                    if let Some(token) = end_token(i) {
-                        self.add_token(token, start_index..self.utf8_index)
-                    }
-                    break Ok(());
-                }
-                [] => {
-                    if let Some(token) = end_token(i) {
-                        self.add_token(token, start_index..self.utf8_index)
-                    }
-                    break Ok(());
-                }
-                [_, ..] => {
-                    self.advance_by(1);
-                    i += 1;
-                }
+        while let [c, ..] = self.view().as_slice() {
+            if !is_valid_identifier_character(*c) {
+                break;
             }
+            self.advance_by(1);
         }
+
+        let token = match &self.source[start_index..self.utf8_index] {
+            "if" => Token::Keyword(If),
+            "else" => Token::Keyword(Else),
+            "while" => Token::Keyword(While),
+            "for" => Token::Keyword(For),
+            "true" => Token::Keyword(True),
+            "false" => Token::Keyword(False),
+            "fn" => Token::Keyword(Fn),
+            "let" => Token::Keyword(Let),
+            "match" => Token::Keyword(Match),
+            "break" => Token::Keyword(Break),
+            "continue" => Token::Keyword(Continue),
+            "do" => Token::Keyword(Do),
+            "import" => Token::Keyword(Import),
+            id => Token::Id(id.to_owned())
+        };
+        self.add_token(token, start_index..self.utf8_index);
+        Ok(())
     }
 
     fn process_operators_and_punctuation(&mut self) -> Result<(), LexerError> {
-        let token = match self.chars {
+        let token = match self.view().as_slice() {
+            ['=', '>', ..] => Some((2, Token::FatArrow)),
             ['=', ..] => Some((1, Token::Equal)),
+            ['!', ..] => Some((1, Token::ExclamationMark)),
             ['(', ..] => Some((1, Token::LeftParenthesis)),
             [')', ..] => Some((1, Token::RightParenthesis)),
             ['[', ..] => Some((1, Token::LeftSquareBracket)),
@@ -168,16 +192,12 @@ impl<'a> Lexer<'a> {
         let start_index = self.utf8_index;
 
         self.advance_by(1); // Eat first quote
-        let string_start = self.chars;
-        let mut i = 0;
+        let string_start_index = self.utf8_index;
         loop {
-            match self.chars {
-                ['\\', '"', ..] => {
-                    self.advance_by(2);
-                    i += 2;
-                }
+            match self.view().as_slice() {
+                ['\\', '"', ..] => self.advance_by(2),
                 ['"', ..] => {
-                    let string = string_start[..i].iter().collect::<String>();
+                    let string = self.source[string_start_index..self.utf8_index].to_owned();
                     self.advance_by(1); // Eat last quote
 
                     let token = Token::Literal(Literal::String(string));
@@ -185,10 +205,7 @@ impl<'a> Lexer<'a> {
 
                     break Ok(());
                 }
-                [_, ..] => {
-                    self.advance_by(1);
-                    i += 1;
-                }
+                [_, ..] => self.advance_by(1),
                 [] => break Ok(()),
             }
         }
@@ -196,42 +213,43 @@ impl<'a> Lexer<'a> {
 
     fn process_numeric_literals(&mut self) -> Result<(), LexerError> {
         let start_index = self.utf8_index;
-        let start = self.chars;
-        let mut i = 0;
         let mut is_float = false;
 
         let mut is_sign_allowed = true;
         let mut is_point_allowed = true;
 
         loop {
-            match self.chars {
+            match self.view().as_slice() {
                 ['+' | '-', ..] if is_sign_allowed => {
                     is_sign_allowed = false;
-
                     self.advance_by(1);
-                    i += 1;
                 }
                 ['.', ..] if is_point_allowed => {
                     is_point_allowed = false;
                     is_sign_allowed = false;
                     is_float = true;
-
                     self.advance_by(1);
-                    i += 1;
                 }
                 [d, ..] if d.is_ascii_digit() => {
                     is_sign_allowed = false;
-
                     self.advance_by(1);
-                    i += 1;
+                }
+                // 10L is a long
+                ['L', ..] if !is_float => {
+                    let long = self.source[start_index..self.utf8_index].parse::<i64>();
+                    self.advance_by(1);
+                    let long = long.map_err(|_| LexerError::NumberOutOfRange(start_index..self.utf8_index))?;
+                    self.add_token(Token::Literal(Literal::Long(long)), start_index..self.utf8_index);
+                    break Ok(());
                 }
                 _ => {
-                    let number = &start[..i].iter().collect::<String>();
+                    let number = &self.source[start_index..self.utf8_index];
                     let token = if is_float {
-                        let float = number.parse::<f32>().unwrap();
+                        let float = number.parse::<f64>().unwrap();
                         Token::Literal(Literal::Float(float))
                     } else {
-                        let integer = number.parse::<i32>().unwrap();
+                        let integer = number.parse::<i32>()
+                            .map_err(|_| LexerError::NumberOutOfRange(start_index..self.utf8_index))?;
                         Token::Literal(Literal::Integer(integer))
                     };
                     self.add_token(token, start_index..self.utf8_index);
@@ -243,7 +261,7 @@ impl<'a> Lexer<'a> {
 
     fn process_comments(&mut self) -> Result<(), LexerError> {
         loop {
-            match self.chars {
+            match self.view().as_slice() {
                 ['\n', ..] | [] => break Ok(()),
                 _ => self.advance_by(1)
             }
@@ -251,17 +269,100 @@ impl<'a> Lexer<'a> {
     }
 }
 
+/// One token per line, preceded by its byte range in the source.
+pub fn dump_tokens(tokens: &[Token], indices: &[Range<usize>]) -> String {
+    let mut dump = String::new();
+    for (token, range) in tokens.iter().zip(indices) {
+        dump += &format!("{:<12} {:?}\n", format!("{}..{}", range.start, range.end), token);
+    }
+    dump
+}
+
 fn is_valid_identifier_character(c: char) -> bool {
     match c {
         '(' | ')' | '[' | ']' | '{' | '}' => false,
         c if c.is_whitespace() => false,
+        // Most likely pasted from a word processor, reported as an error with a suggestion
+        c if ascii_lookalike(c).is_some() => false,
         _ => true,
     }
 }
 
+/// The ASCII character that `c` was probably meant to be.
+pub fn ascii_lookalike(c: char) -> Option<char> {
+    match c {
+        '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{2033}' => Some('"'),
+        '\u{2212}' | '\u{2013}' | '\u{2014}' => Some('-'),
+        '\u{FF08}' => Some('('),
+        '\u{FF09}' => Some(')'),
+        _ => None,
+    }
+}
+
 fn is_punctuation(c: char) -> bool {
     match c {
         '=' | '(' | ')' | '[' | ']' | '{' | '}' => true,
         _ => false,
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literals(source: &str) -> Vec<String> {
+        let (tokens, _) = Lexer::new(source).tokenize().unwrap();
+        tokens.iter().map(|token| format!("{:?}", token)).collect()
+    }
+
+    #[test]
+    fn integer_and_long_literals() {
+        assert_eq!(literals("-2147483648 10L -9223372036854775808L 2.5"), vec![
+            "Literal(Integer(-2147483648))",
+            "Literal(Long(10))",
+            "Literal(Long(-9223372036854775808))",
+            "Literal(Float(2.5))",
+        ]);
+    }
+
+    #[test]
+    fn literals_out_of_range_are_errors() {
+        assert!(matches!(Lexer::new("(+ 1 2147483648)").tokenize(), Err(LexerError::NumberOutOfRange(span)) if span == (5..15)));
+        assert!(matches!(Lexer::new("99999999999999999999L").tokenize(), Err(LexerError::NumberOutOfRange(span)) if span == (0..21)));
+    }
+
+    #[test]
+    fn token_dump() {
+        let source = "let x = 2\n(println \"é\" x)";
+        let (tokens, indices) = Lexer::new(source).tokenize().unwrap();
+        assert_eq!(dump_tokens(&tokens, &indices), r#"0..3         Keyword(Let)
+4..5         Id("x")
+6..7         Equal
+8..9         Literal(Integer(2))
+10..11       LeftParenthesis
+11..18       Id("println")
+19..23       Literal(String("é"))
+24..25       Id("x")
+25..26       RightParenthesis
+"#);
+    }
+
+    #[test]
+    fn large_inputs_give_the_same_tokens_as_their_parts() {
+        let part = "let héllo = \"日本 語\" (println héllo [1 2.5 3L] { -4 }) match x { _ => !ok }\n";
+        let (part_tokens, part_indices) = Lexer::new(part).tokenize().unwrap();
+        // Spans are byte ranges, multi-byte characters count for several bytes
+        assert_eq!(part_indices[..4], [0..3, 4..10, 11..12, 13..25]);
+        let source = part.repeat(10_000);
+        let (tokens, indices) = Lexer::new(&source).tokenize().unwrap();
+
+        assert_eq!(tokens.len(), part_tokens.len() * 10_000);
+        for (i, (token, range)) in tokens.iter().zip(&indices).enumerate() {
+            let (part_token, part_range) = (&part_tokens[i % part_tokens.len()], &part_indices[i % part_tokens.len()]);
+            let offset = i / part_tokens.len() * part.len();
+            assert_eq!(format!("{:?}", token), format!("{:?}", part_token));
+            assert_eq!(*range, part_range.start + offset..part_range.end + offset);
+            assert_eq!(&source[range.clone()], &part[part_range.clone()]);
+        }
+    }
+}
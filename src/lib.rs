@@ -0,0 +1,83 @@
+#![feature(or_patterns)]
+#![feature(box_patterns)]
+#![feature(exact_size_is_empty)]
+
+use std::{panic, thread};
+
+use crate::interpreter::Settings;
+
+pub use crate::interpreter::{InterpreterError, InterpreterErrorWithSpan, set_float_precision, set_recursion_limit};
+pub use crate::lexer::{Lexer, LexerError, Token};
+pub use crate::native_functions::create_global_context_with_native_functions;
+pub use crate::parser::{Context, Expression, ExpressionWithMetadata, Function, Parser, ParserError, Value};
+
+pub mod lexer;
+pub mod parser;
+pub mod interpreter;
+pub mod native_functions;
+pub mod errors;
+pub mod repl;
+pub mod cli;
+pub mod formatter;
+pub mod symbol;
+pub mod lint;
+
+#[derive(Debug)]
+pub enum RunError {
+    LexerError(LexerError),
+    ParserError(ParserError),
+    InterpreterError(InterpreterErrorWithSpan),
+}
+
+impl From<LexerError> for RunError {
+    fn from(e: LexerError) -> Self { Self::LexerError(e) }
+}
+
+impl From<ParserError> for RunError {
+    fn from(e: ParserError) -> Self { Self::ParserError(e) }
+}
+
+impl From<InterpreterErrorWithSpan> for RunError {
+    fn from(e: InterpreterErrorWithSpan) -> Self { Self::InterpreterError(e) }
+}
+
+/// The result of an evaluation, moved out of the thread that evaluated it.
+struct Evaluated(Result<Value, RunError>);
+
+// Safety: the values are only shared through `Rc`s within the evaluation. The evaluating thread
+// has exited when the result is moved out of it, so nothing else refers to them anymore.
+unsafe impl Send for Evaluated {}
+
+/// Lexes, parses and evaluates `source` in a fresh global context.
+/// Returns the value of the last top-level expression.
+/// The evaluation runs on a thread with a stack large enough for the recursion limit, with the
+/// settings of the calling thread.
+pub fn run(source: &str) -> Result<Value, RunError> {
+    let settings = Settings::current();
+    let evaluated = thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(settings.stack_size())
+            .spawn_scoped(scope, || {
+                settings.apply();
+                Evaluated(evaluate(source))
+            })
+            .expect("cannot spawn the interpreter thread")
+            .join()
+    });
+    match evaluated {
+        Ok(Evaluated(result)) => result,
+        Err(panic) => panic::resume_unwind(panic),
+    }
+}
+
+fn evaluate(source: &str) -> Result<Value, RunError> {
+    let (tokens, indices) = Lexer::new(source).tokenize()?;
+    let expressions = Parser::new((tokens.as_slice(), indices.as_slice())).parse()?;
+
+    let global_context = create_global_context_with_native_functions();
+    let mut value = Value::Unit;
+    for expression in &expressions {
+        value = expression.evaluate(global_context.clone())?;
+    }
+    Ok(value)
+}
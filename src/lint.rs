@@ -0,0 +1,161 @@
+/// Static checks on the syntax tree that don't prevent the program from running.
+use std::collections::HashSet;
+
+use crate::parser::{Expression, ExpressionWithMetadata, Label};
+use crate::symbol::Symbol;
+
+struct Binding {
+    label: Label,
+    used: bool,
+}
+
+#[derive(Default)]
+struct Scope {
+    bindings: Vec<Binding>,
+    // Names read before being declared. Function bodies look variables up when they are called,
+    // so they can refer to variables declared after them.
+    forward_references: HashSet<Symbol>,
+}
+
+#[derive(Default)]
+struct UnusedVariables {
+    scopes: Vec<Scope>,
+    unused: Vec<Label>,
+}
+
+/// Returns the `let` declarations whose variable is never read.
+/// Names starting with an underscore are never reported.
+pub fn find_unused_variables(expressions: &[ExpressionWithMetadata]) -> Vec<Label> {
+    let mut analysis = UnusedVariables::default();
+    analysis.visit_scope(expressions, &[]);
+    analysis.unused
+}
+
+impl UnusedVariables {
+    fn visit_scope(&mut self, expressions: &[ExpressionWithMetadata], parameters: &[Label]) {
+        let mut scope = Scope::default();
+        for parameter in parameters {
+            // Only declarations are reported
+            scope.bindings.push(Binding { label: parameter.clone(), used: true });
+        }
+        self.scopes.push(scope);
+
+        for expression in expressions {
+            self.visit(&expression.expression);
+        }
+
+        let scope = self.scopes.pop().unwrap();
+        self.unused.extend(scope.bindings.into_iter()
+            .filter(|b| !b.used && !b.label.label.as_str().starts_with('_'))
+            .map(|b| b.label));
+    }
+
+    fn visit(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Id(id) => self.read(*id),
+            Expression::Value(_) | Expression::Import(_) | Expression::Break | Expression::Continue => (),
+            Expression::Declaration(name, rhs) => {
+                // The right hand side sees the previous variable with the same name
+                self.visit(&rhs.expression);
+                self.declare(name.clone(), false);
+            }
+            Expression::Assignment(_, rhs) => self.visit(&rhs.expression),
+            Expression::Scope(expressions) => self.visit_scope(expressions, &[]),
+            Expression::List(elements) => self.visit_all(elements),
+            Expression::NamedFunctionDefinition { name, parameters, body } => {
+                self.declare(name.clone(), true);
+                self.visit_scope(std::slice::from_ref(body), parameters);
+            }
+            Expression::AnonymousFunctionDefinition { parameters, body } => {
+                self.visit_scope(std::slice::from_ref(body), parameters);
+            }
+            Expression::FunctionCall(function_ptr, arguments) => {
+                self.visit(&function_ptr.expression);
+                self.visit_all(arguments);
+            }
+            Expression::If { guard, base_case } => {
+                self.visit(&guard.expression);
+                self.visit(&base_case.expression);
+            }
+            Expression::IfElse { guard, base_case, else_case } => {
+                self.visit(&guard.expression);
+                self.visit(&base_case.expression);
+                self.visit(&else_case.expression);
+            }
+            Expression::While { guard, body } => {
+                self.visit(&guard.expression);
+                self.visit(&body.expression);
+            }
+            Expression::UnaryOp(_, operand) => self.visit(&operand.expression),
+            Expression::DoWhile { body, guard } => {
+                self.visit(&body.expression);
+                self.visit(&guard.expression);
+            }
+            Expression::Match { scrutinee, arms } => {
+                self.visit(&scrutinee.expression);
+                for arm in arms {
+                    self.visit(&arm.body.expression);
+                }
+            }
+        }
+    }
+
+    fn visit_all(&mut self, expressions: &[ExpressionWithMetadata]) {
+        for expression in expressions {
+            self.visit(&expression.expression);
+        }
+    }
+
+    fn declare(&mut self, label: Label, used: bool) {
+        let scope = self.scopes.last_mut().unwrap();
+        let used = used || scope.forward_references.contains(&label.label);
+        scope.bindings.push(Binding { label, used });
+    }
+
+    fn read(&mut self, name: Symbol) {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(binding) = scope.bindings.iter_mut().rev().find(|b| b.label.label == name) {
+                binding.used = true;
+                return;
+            }
+        }
+        for scope in &mut self.scopes {
+            scope.forward_references.insert(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    use super::*;
+
+    fn unused_variables(source: &str) -> Vec<(&'static str, std::ops::Range<usize>)> {
+        let (tokens, indices) = Lexer::new(source).tokenize().unwrap();
+        let expressions = Parser::new((tokens.as_slice(), indices.as_slice())).parse().unwrap();
+        find_unused_variables(&expressions).into_iter()
+            .map(|label| (label.label.as_str(), label.span))
+            .collect()
+    }
+
+    #[test]
+    fn one_unused_and_one_used() {
+        assert_eq!(unused_variables("let used = 1 let unused = 2 (println used)"), vec![("unused", 17..23)]);
+    }
+
+    #[test]
+    fn shadowed_variables() {
+        // The first x is only read by the declaration shadowing it
+        assert!(unused_variables("let x = 1 let x = (+ x 1) (println x)").is_empty());
+        // The inner x shadows the outer one, which is never read
+        assert_eq!(unused_variables("let x = 1 { let x = 2 (println x) }"), vec![("x", 4..5)]);
+    }
+
+    #[test]
+    fn functions_can_read_variables_declared_after_them() {
+        assert!(unused_variables("fn f () later let later = 1 (f)").is_empty());
+        assert!(unused_variables("let _ignored = 1").is_empty());
+    }
+}
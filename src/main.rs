@@ -1,36 +1,68 @@
-#![feature(or_patterns)]
-#![feature(box_patterns)]
 #![feature(try_blocks)]
-#![feature(exact_size_is_empty)]
 
-use std::{env, process};
+use std::{env, process, thread};
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
 
 use codespan_reporting::files::SimpleFiles;
 
-use crate::native_functions::create_global_context_with_native_functions;
-use crate::interpreter::{InterpreterError, InterpreterErrorWithSpan};
-use crate::lexer::{Lexer, LexerError};
-use crate::parser::{Parser, ParserError};
-use crate::errors::{show_lexer_error, show_parser_error, show_interpreter_error};
-
-mod lexer;
-mod parser;
-mod interpreter;
-mod native_functions;
-mod errors;
+use rusp::cli::{Command, Emit, parse_args, USAGE};
+use rusp::native_functions::create_global_context_with_native_functions;
+use rusp::interpreter::{DEFAULT_STACK_SIZE, InterpreterError, InterpreterErrorWithSpan, set_script_path};
+use rusp::lexer::{dump_tokens, Lexer, LexerError};
+use rusp::parser::{dump_ast, Parser, ParserError};
+use rusp::errors::{flush_diagnostics, show_lexer_error, show_parser_error, show_interpreter_error, show_unused_variable_warnings, set_error_format};
+use rusp::lint::find_unused_variables;
+use rusp::repl::run_repl;
 
 fn main() -> Result<(), AllErrors> {
-    let mut args = env::args();
-    let path = args.next().unwrap();
+    // Deeply recursive scripts need a larger stack than the main thread has
+    let interpreter = thread::Builder::new()
+        .stack_size(DEFAULT_STACK_SIZE)
+        .spawn(|| {
+            let result = run();
+            flush_diagnostics();
+            result
+        })
+        .expect("cannot spawn the interpreter thread");
+    match interpreter.join() {
+        Ok(result) => result,
+        // The panic message was already printed
+        Err(_) => process::exit(101),
+    }
+}
+
+fn run() -> Result<(), AllErrors> {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        Ok(Command::Version) => {
+            println!("rusp {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
 
-    let script_path = match args.next() {
+    set_error_format(options.error_format);
+
+    let script_path = match options.script_path {
         Some(path) => path,
         None => {
-            println!("TODO: REPL");
-            println!("Usage: {} <file>", path);
-            return Ok(());
+            match run_repl(BufReader::new(io::stdin()), io::stdout()) {
+                Ok(code) => process::exit(code),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(2);
+                }
+            }
         }
     };
 
@@ -51,31 +83,52 @@ fn main() -> Result<(), AllErrors> {
     };
 
 
+    set_script_path(Path::new(&script_path));
+
     let mut files = SimpleFiles::new();
     let source_file = files.add(script_path, &source);
 
-    let tokens_with_metadata = {
-        let chars = source.chars().collect::<Vec<_>>();
-        Lexer::new(chars.as_slice()).tokenize()
-    };
+    let tokens_with_metadata = Lexer::new(&source).tokenize();
 
     let tokens_with_metadata = match tokens_with_metadata {
         Ok(t) => t,
         Err(err) => {
             show_lexer_error(err, source_file, files);
+            if options.check {
+                exit(1);
+            }
             return Ok(());
         }
     };
 
+    if let Some(Emit::Tokens) = options.emit {
+        print!("{}", dump_tokens(&tokens_with_metadata.0, &tokens_with_metadata.1));
+        return Ok(());
+    }
+
     let expressions = Parser::new((tokens_with_metadata.0.as_slice(), tokens_with_metadata.1.as_slice())).parse();
     let expressions = match expressions {
         Ok(e) => e,
         Err(err) => {
             show_parser_error(err, source_file, files);
+            if options.check {
+                exit(1);
+            }
             return Ok(());
         }
     };
 
+    if let Some(Emit::Ast) = options.emit {
+        print!("{}", dump_ast(&expressions));
+        return Ok(());
+    }
+
+    show_unused_variable_warnings(&find_unused_variables(&expressions), source_file, &files);
+
+    if options.check {
+        return Ok(());
+    }
+
     let global_context = create_global_context_with_native_functions();
 
     let result: Result<(), InterpreterErrorWithSpan> = try {
@@ -85,12 +138,23 @@ fn main() -> Result<(), AllErrors> {
     };
 
     if let Err(err) = result {
+        if let InterpreterError::Exit(code) = err.error {
+            exit(code);
+        }
         show_interpreter_error(err, source_file, files);
     }
 
     Ok(())
 }
 
+/// Exits from the interpreter thread, writing what is still buffered first.
+fn exit(code: i32) -> ! {
+    flush_diagnostics();
+    // process::exit doesn't run destructors
+    let _ = io::stdout().flush();
+    process::exit(code)
+}
+
 #[derive(Debug)]
 enum AllErrors {
     LexerError(LexerError),
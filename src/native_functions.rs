@@ -1,15 +1,19 @@
 use std::cell::RefCell;
-use std::io::Write;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufRead, Write};
 use std::rc::Rc;
 
 use crate::interpreter::{InterpreterError, InterpreterErrorWithSpan};
 use crate::parser::{Context, Function, Value};
+use crate::symbol::Symbol;
 
 pub fn add_native_function(
     context: &mut Rc<RefCell<Context>>,
     name: &str,
     fn_pointer: fn(Rc<RefCell<Context>>, Vec<Value>) -> Result<Value, InterpreterErrorWithSpan>) {
-    context.borrow_mut().variables.insert(name.to_owned(), Value::Function(Function::NativeFunction {
+    context.borrow_mut().variables.insert(Symbol::intern(name), Value::Function(Function::NativeFunction {
         closing_context: context.clone(),
         name: name.to_owned(),
         fn_pointer,
@@ -19,87 +23,52 @@ pub fn add_native_function(
 pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
     let mut global_context = Rc::new(RefCell::new(Context::default()));
 
+    // Comparisons are chained: each argument is compared with the next one, (< 1 2.0 3) is true.
+    // With less than two arguments there is nothing to compare and the result is true.
     add_native_function(&mut global_context, "==", |_context, arguments| {
-        use Value::*;
-        let result = arguments.windows(2).all(|slice| {
-            match (&slice[0], &slice[1]) {
-                (Boolean(x), Boolean(y)) => x == y,
-                (Integer(x), Integer(y)) => x == y,
-                (Float(x), Float(y)) => x == y,
-                (String(x), String(y)) => x == y,
-                _ => false,
-            }
-        });
-        Ok(Boolean(result))
+        let result = arguments.windows(2).all(|slice| values_equal(&slice[0], &slice[1]));
+        Ok(Value::Boolean(result))
     });
 
     add_native_function(&mut global_context, "!=", |_context, arguments| {
-        use Value::*;
-        let result = arguments.windows(2).all(|slice| {
-            match (&slice[0], &slice[1]) {
-                (Boolean(x), Boolean(y)) => x != y,
-                (Integer(x), Integer(y)) => x != y,
-                (Float(x), Float(y)) => x != y,
-                (String(x), String(y)) => x != y,
-                _ => false,
-            }
-        });
-        Ok(Boolean(result))
+        let result = arguments.windows(2).all(|slice| !values_equal(&slice[0], &slice[1]));
+        Ok(Value::Boolean(result))
     });
 
     add_native_function(&mut global_context, "<", |_context, arguments| {
-        use Value::*;
+        check_orderable(&arguments)?;
         let result = arguments.windows(2).all(|slice| {
-            match (&slice[0], &slice[1]) {
-                (Integer(x), Integer(y)) => x < y,
-                (Float(x), Float(y)) => x < y,
-                (String(x), String(y)) => x < y,
-                _ => false,
-            }
+            compare_values(&slice[0], &slice[1]) == Some(Ordering::Less)
         });
-        Ok(Boolean(result))
+        Ok(Value::Boolean(result))
     });
 
     add_native_function(&mut global_context, ">", |_context, arguments| {
-        use Value::*;
+        check_orderable(&arguments)?;
         let result = arguments.windows(2).all(|slice| {
-            match (&slice[0], &slice[1]) {
-                (Integer(x), Integer(y)) => x > y,
-                (Float(x), Float(y)) => x > y,
-                (String(x), String(y)) => x > y,
-                _ => false,
-            }
+            compare_values(&slice[0], &slice[1]) == Some(Ordering::Greater)
         });
-        Ok(Boolean(result))
+        Ok(Value::Boolean(result))
     });
 
     add_native_function(&mut global_context, "<=", |_context, arguments| {
-        use Value::*;
+        check_orderable(&arguments)?;
         let result = arguments.windows(2).all(|slice| {
-            match (&slice[0], &slice[1]) {
-                (Integer(x), Integer(y)) => x <= y,
-                (Float(x), Float(y)) => x <= y,
-                (String(x), String(y)) => x <= y,
-                _ => false,
-            }
+            matches!(compare_values(&slice[0], &slice[1]), Some(Ordering::Less | Ordering::Equal))
         });
-        Ok(Boolean(result))
+        Ok(Value::Boolean(result))
     });
 
     add_native_function(&mut global_context, ">=", |_context, arguments| {
-        use Value::*;
+        check_orderable(&arguments)?;
         let result = arguments.windows(2).all(|slice| {
-            match (&slice[0], &slice[1]) {
-                (Integer(x), Integer(y)) => x >= y,
-                (Float(x), Float(y)) => x >= y,
-                (String(x), String(y)) => x >= y,
-                _ => false,
-            }
+            matches!(compare_values(&slice[0], &slice[1]), Some(Ordering::Greater | Ordering::Equal))
         });
-        Ok(Boolean(result))
+        Ok(Value::Boolean(result))
     });
 
     add_native_function(&mut global_context, "+", |_context, arguments| {
+        check_operands(&arguments, is_number_or_string)?;
         let mut iter = arguments.into_iter();
         let first = iter.next().ok_or(InterpreterError::WrongNumberOfArguments.into());
 
@@ -109,12 +78,21 @@ pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
                 match (acc, x) {
                     (String(lhs), String(rhs)) => Ok(String(format!("{}{}", lhs, rhs))),
                     (String(lhs), Integer(rhs)) => Ok(String(format!("{}{}", lhs, rhs))),
-                    (String(lhs), Float(rhs)) => Ok(String(format!("{}{}", lhs, rhs))),
+                    (String(lhs), Float(rhs)) => Ok(String(format!("{}{}", lhs, Float(rhs)))),
+                    (String(lhs), Long(rhs)) => Ok(String(format!("{}{}", lhs, rhs))),
                     (Integer(lhs), String(rhs)) => Ok(String(format!("{}{}", lhs, rhs))),
-                    (Integer(lhs), Integer(rhs)) => Ok(Integer(lhs + rhs)),
-                    (Integer(lhs), Float(rhs)) => Ok(Float(lhs as f32 + rhs)),
-                    (Float(lhs), String(rhs)) => Ok(String(format!("{}{}", lhs, rhs))),
-                    (Float(lhs), Integer(rhs)) => Ok(Float(lhs + rhs as f32)),
+                    (Long(lhs), String(rhs)) => Ok(String(format!("{}{}", lhs, rhs))),
+                    (Integer(lhs), Integer(rhs)) => lhs.checked_add(rhs)
+                        .map(Integer)
+                        .ok_or_else(|| InterpreterError::IntegerOverflow("+".to_owned()).into()),
+                    (lhs @ (Integer(_) | Long(_)), rhs @ (Integer(_) | Long(_))) => {
+                        long_operation(&lhs, &rhs, "+", i64::checked_add)
+                    }
+                    (Integer(lhs), Float(rhs)) => Ok(Float(lhs as f64 + rhs)),
+                    (Long(lhs), Float(rhs)) => Ok(Float(lhs as f64 + rhs)),
+                    (Float(lhs), String(rhs)) => Ok(String(format!("{}{}", Float(lhs), rhs))),
+                    (Float(lhs), Integer(rhs)) => Ok(Float(lhs + rhs as f64)),
+                    (Float(lhs), Long(rhs)) => Ok(Float(lhs + rhs as f64)),
                     (Float(lhs), Float(rhs)) => Ok(Float(lhs + rhs)),
                     _ => Err(InterpreterError::InvalidOperands.into()),
                 }
@@ -122,7 +100,12 @@ pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
         })
     });
 
+    // (- x) negates x
     add_native_function(&mut global_context, "-", |_context, arguments| {
+        check_operands(&arguments, is_number)?;
+        if let [value] = arguments.as_slice() {
+            return negate(value).map_err(Into::into);
+        }
         let mut iter = arguments.into_iter();
         let first = iter.next().ok_or(InterpreterError::WrongNumberOfArguments.into());
 
@@ -130,9 +113,16 @@ pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
             use Value::*;
             acc.and_then(|acc| {
                 match (acc, x) {
-                    (Integer(lhs), Integer(rhs)) => Ok(Integer(lhs - rhs)),
-                    (Integer(lhs), Float(rhs)) => Ok(Float(lhs as f32 - rhs)),
-                    (Float(lhs), Integer(rhs)) => Ok(Float(lhs - rhs as f32)),
+                    (Integer(lhs), Integer(rhs)) => lhs.checked_sub(rhs)
+                        .map(Integer)
+                        .ok_or_else(|| InterpreterError::IntegerOverflow("-".to_owned()).into()),
+                    (lhs @ (Integer(_) | Long(_)), rhs @ (Integer(_) | Long(_))) => {
+                        long_operation(&lhs, &rhs, "-", i64::checked_sub)
+                    }
+                    (Integer(lhs), Float(rhs)) => Ok(Float(lhs as f64 - rhs)),
+                    (Long(lhs), Float(rhs)) => Ok(Float(lhs as f64 - rhs)),
+                    (Float(lhs), Integer(rhs)) => Ok(Float(lhs - rhs as f64)),
+                    (Float(lhs), Long(rhs)) => Ok(Float(lhs - rhs as f64)),
                     (Float(lhs), Float(rhs)) => Ok(Float(lhs - rhs)),
                     _ => Err(InterpreterError::InvalidOperands.into()),
                 }
@@ -141,6 +131,7 @@ pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
     });
 
     add_native_function(&mut global_context, "*", |_context, arguments| {
+        check_operands(&arguments, is_number)?;
         let mut iter = arguments.into_iter();
         let first = iter.next().ok_or(InterpreterError::WrongNumberOfArguments.into());
 
@@ -148,9 +139,16 @@ pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
             use Value::*;
             acc.and_then(|acc| {
                 match (acc, x) {
-                    (Integer(lhs), Integer(rhs)) => Ok(Integer(lhs * rhs)),
-                    (Integer(lhs), Float(rhs)) => Ok(Float(lhs as f32 * rhs)),
-                    (Float(lhs), Integer(rhs)) => Ok(Float(lhs * rhs as f32)),
+                    (Integer(lhs), Integer(rhs)) => lhs.checked_mul(rhs)
+                        .map(Integer)
+                        .ok_or_else(|| InterpreterError::IntegerOverflow("*".to_owned()).into()),
+                    (lhs @ (Integer(_) | Long(_)), rhs @ (Integer(_) | Long(_))) => {
+                        long_operation(&lhs, &rhs, "*", i64::checked_mul)
+                    }
+                    (Integer(lhs), Float(rhs)) => Ok(Float(lhs as f64 * rhs)),
+                    (Long(lhs), Float(rhs)) => Ok(Float(lhs as f64 * rhs)),
+                    (Float(lhs), Integer(rhs)) => Ok(Float(lhs * rhs as f64)),
+                    (Float(lhs), Long(rhs)) => Ok(Float(lhs * rhs as f64)),
                     (Float(lhs), Float(rhs)) => Ok(Float(lhs * rhs)),
                     _ => Err(InterpreterError::InvalidOperands.into()),
                 }
@@ -159,6 +157,7 @@ pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
     });
 
     add_native_function(&mut global_context, "/", |_context, arguments| {
+        check_operands(&arguments, is_number)?;
         let mut iter = arguments.into_iter();
         let first = iter.next().ok_or(InterpreterError::WrongNumberOfArguments.into());
 
@@ -166,9 +165,17 @@ pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
             use Value::*;
             acc.and_then(|acc| {
                 match (acc, x) {
-                    (Integer(lhs), Integer(rhs)) => Ok(Integer(lhs / rhs)),
-                    (Integer(lhs), Float(rhs)) => Ok(Float(lhs as f32 / rhs)),
-                    (Float(lhs), Integer(rhs)) => Ok(Float(lhs / rhs as f32)),
+                    (Integer(_) | Long(_), Integer(0) | Long(0)) => Err(InterpreterError::DivisionByZero.into()),
+                    (Integer(lhs), Integer(rhs)) => lhs.checked_div(rhs)
+                        .map(Integer)
+                        .ok_or_else(|| InterpreterError::IntegerOverflow("/".to_owned()).into()),
+                    (lhs @ (Integer(_) | Long(_)), rhs @ (Integer(_) | Long(_))) => {
+                        long_operation(&lhs, &rhs, "/", i64::checked_div)
+                    }
+                    (Integer(lhs), Float(rhs)) => Ok(Float(lhs as f64 / rhs)),
+                    (Long(lhs), Float(rhs)) => Ok(Float(lhs as f64 / rhs)),
+                    (Float(lhs), Integer(rhs)) => Ok(Float(lhs / rhs as f64)),
+                    (Float(lhs), Long(rhs)) => Ok(Float(lhs / rhs as f64)),
                     (Float(lhs), Float(rhs)) => Ok(Float(lhs / rhs)),
                     _ => Err(InterpreterError::InvalidOperands.into()),
                 }
@@ -176,7 +183,35 @@ pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
         })
     });
 
+    add_native_function(&mut global_context, "%", |_context, arguments| {
+        check_operands(&arguments, is_number)?;
+        let mut iter = arguments.into_iter();
+        let first = iter.next().ok_or(InterpreterError::WrongNumberOfArguments.into());
+
+        iter.fold(first, |acc, x| {
+            use Value::*;
+            acc.and_then(|acc| {
+                match (acc, x) {
+                    (Integer(_) | Long(_), Integer(0) | Long(0)) => Err(InterpreterError::DivisionByZero.into()),
+                    (Integer(lhs), Integer(rhs)) => lhs.checked_rem(rhs)
+                        .map(Integer)
+                        .ok_or_else(|| InterpreterError::IntegerOverflow("%".to_owned()).into()),
+                    (lhs @ (Integer(_) | Long(_)), rhs @ (Integer(_) | Long(_))) => {
+                        long_operation(&lhs, &rhs, "%", i64::checked_rem)
+                    }
+                    (Integer(lhs), Float(rhs)) => Ok(Float(lhs as f64 % rhs)),
+                    (Long(lhs), Float(rhs)) => Ok(Float(lhs as f64 % rhs)),
+                    (Float(lhs), Integer(rhs)) => Ok(Float(lhs % rhs as f64)),
+                    (Float(lhs), Long(rhs)) => Ok(Float(lhs % rhs as f64)),
+                    (Float(lhs), Float(rhs)) => Ok(Float(lhs % rhs)),
+                    _ => Err(InterpreterError::InvalidOperands.into()),
+                }
+            })
+        })
+    });
+
     add_native_function(&mut global_context, "**", |_context, arguments| {
+        check_operands(&arguments, is_number)?;
         let mut iter = arguments.into_iter();
         let first = iter.next().ok_or(InterpreterError::WrongNumberOfArguments.into());
 
@@ -184,9 +219,27 @@ pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
             use Value::*;
             acc.and_then(|acc| {
                 match (acc, x) {
-                    (Integer(lhs), Integer(rhs)) => Ok(Float((lhs as f32).powi(rhs))),
-                    (Integer(lhs), Float(rhs)) => Ok(Float((lhs as f32).powf(rhs))),
-                    (Float(lhs), Integer(rhs)) => Ok(Float(lhs.powf(rhs as f32))),
+                    (Integer(lhs), Integer(rhs)) => match u32::try_from(rhs) {
+                        Ok(rhs) => lhs.checked_pow(rhs)
+                            .map(Integer)
+                            .ok_or_else(|| InterpreterError::IntegerOverflow("**".to_owned()).into()),
+                        // Negative exponents give fractions
+                        Err(_) => Ok(Float((lhs as f64).powi(rhs))),
+                    },
+                    (lhs @ (Integer(_) | Long(_)), rhs @ (Integer(_) | Long(_))) => {
+                        let (lhs, rhs) = (as_long(&lhs).unwrap(), as_long(&rhs).unwrap());
+                        match u32::try_from(rhs) {
+                            Ok(rhs) => lhs.checked_pow(rhs)
+                                .map(Long)
+                                .ok_or_else(|| InterpreterError::IntegerOverflow("**".to_owned()).into()),
+                            Err(_) if rhs < 0 => Ok(Float((lhs as f64).powf(rhs as f64))),
+                            Err(_) => Err(InterpreterError::IntegerOverflow("**".to_owned()).into()),
+                        }
+                    }
+                    (Integer(lhs), Float(rhs)) => Ok(Float((lhs as f64).powf(rhs))),
+                    (Long(lhs), Float(rhs)) => Ok(Float((lhs as f64).powf(rhs))),
+                    (Float(lhs), Integer(rhs)) => Ok(Float(lhs.powf(rhs as f64))),
+                    (Float(lhs), Long(rhs)) => Ok(Float(lhs.powf(rhs as f64))),
                     (Float(lhs), Float(rhs)) => Ok(Float(lhs.powf(rhs))),
                     _ => Err(InterpreterError::InvalidOperands.into()),
                 }
@@ -194,6 +247,114 @@ pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
         })
     });
 
+    // Division rounding towards negative infinity, (floor_div -7 2) == -4 while (/ -7 2) == -3
+    add_native_function(&mut global_context, "floor_div", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Integer(_), Value::Integer(0)] => Err(InterpreterError::DivisionByZero.into()),
+            [Value::Integer(lhs), Value::Integer(rhs)] => {
+                let quotient = lhs.checked_div(*rhs)
+                    .ok_or_else(|| InterpreterError::IntegerOverflow("floor_div".to_owned()))?;
+                match lhs % rhs != 0 && (*lhs < 0) != (*rhs < 0) {
+                    true => Ok(Value::Integer(quotient - 1)),
+                    false => Ok(Value::Integer(quotient)),
+                }
+            }
+            [Value::Integer(_) | Value::Long(_), Value::Integer(0) | Value::Long(0)] => {
+                Err(InterpreterError::DivisionByZero.into())
+            }
+            [lhs @ (Value::Integer(_) | Value::Long(_)), rhs @ (Value::Integer(_) | Value::Long(_))] => {
+                let (lhs, rhs) = (as_long(lhs).unwrap(), as_long(rhs).unwrap());
+                let quotient = lhs.checked_div(rhs)
+                    .ok_or_else(|| InterpreterError::IntegerOverflow("floor_div".to_owned()))?;
+                match lhs % rhs != 0 && (lhs < 0) != (rhs < 0) {
+                    true => Ok(Value::Long(quotient - 1)),
+                    false => Ok(Value::Long(quotient)),
+                }
+            }
+            [lhs, rhs] => match (as_float(lhs), as_float(rhs)) {
+                (Some(lhs), Some(rhs)) => Ok(Value::Float((lhs / rhs).floor())),
+                _ => Err(InterpreterError::InvalidOperands.into()),
+            },
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "sqrt", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Integer(x)] => Ok(Value::Float((*x as f64).sqrt())),
+            [Value::Long(x)] => Ok(Value::Float((*x as f64).sqrt())),
+            [Value::Float(x)] => Ok(Value::Float(x.sqrt())),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "abs", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Integer(x)] => x.checked_abs().map(Value::Integer).ok_or_else(|| InterpreterError::IntegerOverflow("abs".to_owned()).into()),
+            [Value::Long(x)] => x.checked_abs().map(Value::Long).ok_or_else(|| InterpreterError::IntegerOverflow("abs".to_owned()).into()),
+            [Value::Float(x)] => Ok(Value::Float(x.abs())),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // floor, ceil and round always return integers, floats out of their range are an error
+    add_native_function(&mut global_context, "floor", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Integer(x)] => Ok(Value::Integer(*x)),
+            [Value::Long(x)] => Ok(Value::Long(*x)),
+            [Value::Float(x)] => float_to_integer(x.floor(), "floor").map(Value::Integer).map_err(Into::into),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "ceil", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Integer(x)] => Ok(Value::Integer(*x)),
+            [Value::Long(x)] => Ok(Value::Long(*x)),
+            [Value::Float(x)] => float_to_integer(x.ceil(), "ceil").map(Value::Integer).map_err(Into::into),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "round", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Integer(x)] => Ok(Value::Integer(*x)),
+            [Value::Long(x)] => Ok(Value::Long(*x)),
+            [Value::Float(x)] => float_to_integer(x.round(), "round").map(Value::Integer).map_err(Into::into),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // min and max return one of their arguments unchanged, so integers stay integers
+    add_native_function(&mut global_context, "min", |_context, arguments| {
+        let mut iter = arguments.into_iter();
+        let first = iter.next().ok_or(InterpreterError::WrongNumberOfArguments.into());
+
+        iter.fold(first, |acc, x| {
+            acc.and_then(|acc| match (as_float(&acc), as_float(&x)) {
+                (Some(lhs), Some(rhs)) => Ok(if rhs < lhs { x } else { acc }),
+                _ => Err(InterpreterError::InvalidOperands.into()),
+            })
+        })
+    });
+
+    add_native_function(&mut global_context, "max", |_context, arguments| {
+        let mut iter = arguments.into_iter();
+        let first = iter.next().ok_or(InterpreterError::WrongNumberOfArguments.into());
+
+        iter.fold(first, |acc, x| {
+            acc.and_then(|acc| match (as_float(&acc), as_float(&x)) {
+                (Some(lhs), Some(rhs)) => Ok(if rhs > lhs { x } else { acc }),
+                _ => Err(InterpreterError::InvalidOperands.into()),
+            })
+        })
+    });
+
     add_native_function(&mut global_context, "!", |_context, arguments| {
         match arguments.as_slice() {
             [Value::Boolean(b)] => Ok(Value::Boolean(!*b)),
@@ -225,18 +386,18 @@ pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
         })
     });
 
-    add_native_function(&mut global_context, "print", |_context, arguments| {
+    add_native_function(&mut global_context, "print", |context, arguments| {
         match arguments.as_slice() {
-            [value] => print!("{}", value),
+            [value] => with_output(&context, |output| write!(output, "{}", value))?,
             _ => return Err(InterpreterError::WrongNumberOfArguments.into()),
         }
         Ok(Value::Unit)
     });
 
-    add_native_function(&mut global_context, "println", |_context, arguments| {
+    add_native_function(&mut global_context, "println", |context, arguments| {
         match arguments.as_slice() {
-            [] => println!(),
-            [value] => println!("{}", value),
+            [] => with_output(&context, |output| writeln!(output))?,
+            [value] => with_output(&context, |output| writeln!(output, "{}", value))?,
             _ => return Err(InterpreterError::WrongNumberOfArguments.into()),
         }
         Ok(Value::Unit)
@@ -259,27 +420,695 @@ pub fn create_global_context_with_native_functions() -> Rc<RefCell<Context>> {
         Ok(Value::Unit)
     });
 
-    add_native_function(&mut global_context, "dbg", |_context, arguments| {
-        println!("{:#?}", &arguments[0]);
-        Ok(Value::Unit)
+    add_native_function(&mut global_context, "dbg", |context, arguments| {
+        match arguments.as_slice() {
+            [value] => {
+                with_output(&context, |output| writeln!(output, "{:#?}", value))?;
+                Ok(Value::Unit)
+            }
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // Negative indices count from the end of the list, (get list -1) is the last element
+    add_native_function(&mut global_context, "get", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::List(list), Value::Integer(index)] => {
+                let list = list.borrow();
+                let index = from_end_index(*index, list.len())?;
+                Ok(list[index].clone())
+            }
+            [Value::Map(map), key] => {
+                map.borrow().iter()
+                    .find(|(k, _)| values_equal(k, key))
+                    .map(|(_, v)| v.clone())
+                    .ok_or(InterpreterError::KeyNotFound.into())
+            }
+            [_, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "push", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::List(list), value] => {
+                list.borrow_mut().push(value.clone());
+                Ok(Value::Unit)
+            }
+            [_, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // Popping from an empty list is an out of bounds access
+    add_native_function(&mut global_context, "pop", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::List(list)] => list.borrow_mut().pop().ok_or(InterpreterError::IndexOutOfBounds.into()),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // (remove list index) returns the removed element
+    add_native_function(&mut global_context, "remove", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::List(list), Value::Integer(index)] => {
+                let index = to_index(*index)?;
+                let mut list = list.borrow_mut();
+                if index >= list.len() {
+                    return Err(InterpreterError::IndexOutOfBounds.into());
+                }
+                Ok(list.remove(index))
+            }
+            [_, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // (insert list index value), an index equal to the length appends the value
+    add_native_function(&mut global_context, "insert", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::List(list), Value::Integer(index), value] => {
+                let index = to_index(*index)?;
+                let mut list = list.borrow_mut();
+                if index > list.len() {
+                    return Err(InterpreterError::IndexOutOfBounds.into());
+                }
+                list.insert(index, value.clone());
+                Ok(Value::Unit)
+            }
+            [_, _, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "reverse", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::List(list)] => {
+                let reversed = list.borrow().iter().rev().cloned().collect();
+                Ok(Value::List(Rc::new(RefCell::new(reversed))))
+            }
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // (range end), (range start end) or (range start end step), end excluded
+    add_native_function(&mut global_context, "range", |_context, arguments| {
+        let (start, end, step) = match arguments.as_slice() {
+            [Value::Integer(end)] => (0, *end, 1),
+            [Value::Integer(start), Value::Integer(end)] => (*start, *end, 1),
+            [Value::Integer(start), Value::Integer(end), Value::Integer(step)] => {
+                if *step == 0 || (start < end && *step < 0) || (start > end && *step > 0) {
+                    return Err(InterpreterError::InvalidOperands.into());
+                }
+                (*start, *end, *step)
+            }
+            [_] | [_, _] | [_, _, _] => return Err(InterpreterError::InvalidOperands.into()),
+            _ => return Err(InterpreterError::WrongNumberOfArguments.into()),
+        };
+
+        let mut values = vec![];
+        let mut i = start as i64;
+        while (step > 0 && i < end as i64) || (step < 0 && i > end as i64) {
+            values.push(Value::Integer(i as i32));
+            i += step as i64;
+        }
+        Ok(Value::List(Rc::new(RefCell::new(values))))
+    });
+
+    // Indices past the end are clamped to the length of the list, and a start past the end
+    // produces an empty list.
+    add_native_function(&mut global_context, "slice", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::List(list), Value::Integer(start), Value::Integer(end)] => {
+                let list = list.borrow();
+                let end = to_index(*end)?.min(list.len());
+                let start = to_index(*start)?.min(end);
+                Ok(Value::List(Rc::new(RefCell::new(list[start..end].to_vec()))))
+            }
+            [_, _, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // (dict key1 value1 key2 value2 ...) builds a map
+    add_native_function(&mut global_context, "dict", |_context, arguments| {
+        if arguments.len() % 2 != 0 {
+            return Err(InterpreterError::WrongNumberOfArguments.into());
+        }
+        let mut map: Vec<(Value, Value)> = vec![];
+        for pair in arguments.chunks(2) {
+            insert_into_map(&mut map, pair[0].clone(), pair[1].clone());
+        }
+        Ok(Value::Map(Rc::new(RefCell::new(map))))
+    });
+
+    add_native_function(&mut global_context, "map", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Function(f), Value::List(list)] => {
+                let elements = list.borrow().clone();
+                let mut results = vec![];
+                for element in elements {
+                    results.push(f.call(vec![element])?);
+                }
+                Ok(Value::List(Rc::new(RefCell::new(results))))
+            }
+            [_, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "filter", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Function(f), Value::List(list)] => {
+                let elements = list.borrow().clone();
+                let mut results = vec![];
+                for element in elements {
+                    // We don't do implicit casting to boolean
+                    if let Value::Boolean(true) = f.call(vec![element.clone()])? {
+                        results.push(element);
+                    }
+                }
+                Ok(Value::List(Rc::new(RefCell::new(results))))
+            }
+            [_, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // (reduce function initial_value list)
+    add_native_function(&mut global_context, "reduce", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Function(f), initial_value, Value::List(list)] => {
+                let elements = list.borrow().clone();
+                let mut accumulator = initial_value.clone();
+                for element in elements {
+                    accumulator = f.call(vec![accumulator, element])?;
+                }
+                Ok(accumulator)
+            }
+            [_, _, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // (set list index value) replaces the element in place: other references to the list see
+    // the new element, while the value previously stored there is left untouched.
+    // Like with get, negative indices count from the end.
+    add_native_function(&mut global_context, "set", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::List(list), Value::Integer(index), value] => {
+                let mut list = list.borrow_mut();
+                let index = from_end_index(*index, list.len())?;
+                list[index] = value.clone();
+                Ok(Value::Unit)
+            }
+            [Value::Map(map), key, value] => {
+                // The key may be the map itself, it is compared before the map is borrowed mutably
+                let position = map.borrow().iter().position(|(k, _)| values_equal(k, key));
+                let mut map = map.borrow_mut();
+                match position {
+                    Some(position) => map[position].1 = value.clone(),
+                    None => map.push((key.clone(), value.clone())),
+                }
+                Ok(Value::Unit)
+            }
+            [_, _, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "keys", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Map(map)] => {
+                let keys = map.borrow().iter().map(|(k, _)| k.clone()).collect();
+                Ok(Value::List(Rc::new(RefCell::new(keys))))
+            }
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "values", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Map(map)] => {
+                let values = map.borrow().iter().map(|(_, v)| v.clone()).collect();
+                Ok(Value::List(Rc::new(RefCell::new(values))))
+            }
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "to_int", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Integer(x)] => Ok(Value::Integer(*x)),
+            [Value::Long(x)] => i32::try_from(*x)
+                .map(Value::Integer)
+                .map_err(|_| InterpreterError::IntegerOverflow("to_int".to_owned()).into()),
+            [Value::Float(x)] => float_to_integer(x.trunc(), "to_int").map(Value::Integer).map_err(Into::into),
+            [Value::String(string)] => string.trim().parse::<i32>()
+                .map(Value::Integer)
+                .map_err(|_| InterpreterError::ParseError(string.to_owned()).into()),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "to_float", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Integer(x)] => Ok(Value::Float(*x as f64)),
+            [Value::Long(x)] => Ok(Value::Float(*x as f64)),
+            [Value::Float(x)] => Ok(Value::Float(*x)),
+            [Value::String(string)] => string.trim().parse::<f64>()
+                .map(Value::Float)
+                .map_err(|_| InterpreterError::ParseError(string.to_owned()).into()),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "to_long", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::Integer(x)] => Ok(Value::Long(*x as i64)),
+            [Value::Long(x)] => Ok(Value::Long(*x)),
+            [Value::Float(x)] => float_to_long(x.trunc(), "to_long").map(Value::Long).map_err(Into::into),
+            [Value::String(string)] => string.trim().parse::<i64>()
+                .map(Value::Long)
+                .map_err(|_| InterpreterError::ParseError(string.to_owned()).into()),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // (to_string float decimals) rounds to a fixed number of decimals, (to_string 3.14159 2) == "3.14"
+    add_native_function(&mut global_context, "to_string", |_context, arguments| {
+        match arguments.as_slice() {
+            [value] => Ok(Value::String(value.to_string())),
+            [Value::Float(float), Value::Integer(precision)] => {
+                let precision = usize::try_from(*precision).map_err(|_| InterpreterError::InvalidOperands)?;
+                Ok(Value::String(format!("{:.*}", precision, float)))
+            }
+            [_, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "typeof", |_context, arguments| {
+        let name = match arguments.as_slice() {
+            [Value::Unit] => "unit",
+            [Value::Integer(_)] => "integer",
+            [Value::Long(_)] => "long",
+            [Value::Float(_)] => "float",
+            [Value::String(_)] => "string",
+            [Value::Boolean(_)] => "boolean",
+            [Value::List(_)] => "list",
+            [Value::Map(_)] => "map",
+            [Value::Function(_)] => "function",
+            _ => return Err(InterpreterError::WrongNumberOfArguments.into()),
+        };
+        Ok(Value::String(name.to_owned()))
+    });
+
+    add_native_function(&mut global_context, "assert", |_context, arguments| {
+        let (condition, message) = match arguments.as_slice() {
+            [Value::Boolean(b)] => (*b, "assertion failed".to_owned()),
+            [Value::Boolean(b), Value::String(message)] => (*b, message.to_owned()),
+            [_] | [_, _] => return Err(InterpreterError::InvalidOperands.into()),
+            _ => return Err(InterpreterError::WrongNumberOfArguments.into()),
+        };
+        match condition {
+            true => Ok(Value::Unit),
+            false => Err(InterpreterError::AssertionFailed(message).into()),
+        }
+    });
+
+    // Strings count characters (Unicode scalar values), not bytes: (len "héllo") == 5
+    add_native_function(&mut global_context, "len", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::String(string)] => Ok(Value::Integer(string.chars().count() as i32)),
+            [Value::List(list)] => Ok(Value::Integer(list.borrow().len() as i32)),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "to_upper", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::String(string)] => Ok(Value::String(string.to_uppercase())),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "to_lower", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::String(string)] => Ok(Value::String(string.to_lowercase())),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "trim", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::String(string)] => Ok(Value::String(string.trim().to_owned())),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "contains", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::String(string), Value::String(pattern)] => Ok(Value::Boolean(string.contains(pattern.as_str()))),
+            [Value::List(list), value] => Ok(Value::Boolean(list.borrow().iter().any(|v| values_equal(v, value)))),
+            [_, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "replace", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::String(string), Value::String(from), Value::String(to)] => {
+                Ok(Value::String(string.replace(from.as_str(), to)))
+            }
+            [_, _, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // Indices count characters, not bytes: (substring "héllo" 1 3) == "él"
+    add_native_function(&mut global_context, "substring", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::String(string), Value::Integer(start), Value::Integer(end)] => {
+                let (start, end) = (to_index(*start)?, to_index(*end)?);
+                if start > end || end > string.chars().count() {
+                    return Err(InterpreterError::IndexOutOfBounds.into());
+                }
+                Ok(Value::String(string.chars().skip(start).take(end - start).collect()))
+            }
+            [_, _, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // An empty separator splits the string into its characters
+    add_native_function(&mut global_context, "split", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::String(string), Value::String(separator)] => {
+                let parts = match separator.is_empty() {
+                    true => string.chars().map(|c| Value::String(c.to_string())).collect(),
+                    false => string.split(separator.as_str()).map(|s| Value::String(s.to_owned())).collect(),
+                };
+                Ok(Value::List(Rc::new(RefCell::new(parts))))
+            }
+            [_, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    // Elements that aren't strings are joined using their printed representation
+    add_native_function(&mut global_context, "join", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::List(list), Value::String(separator)] => {
+                let parts = list.borrow().iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                Ok(Value::String(parts.join(separator)))
+            }
+            [_, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
     });
 
-    add_native_function(&mut global_context, "input", |_context, arguments| {
+    // (format "{} + {} = {}" 1 2 3), {{ and }} produce literal braces
+    add_native_function(&mut global_context, "format", |_context, arguments| {
         match arguments.as_slice() {
-            [] => (),
-            [to_print] => print!("{}", to_print),
-            _ => return Err(InterpreterError::WrongNumberOfArguments.into())
+            [Value::String(template), arguments @ ..] => format_template(template, arguments).map(Value::String),
+            [_, ..] => Err(InterpreterError::InvalidOperands.into()),
+            [] => Err(InterpreterError::WrongNumberOfArguments.into()),
         }
-        std::io::stdout().flush().map_err(|_| InterpreterError::StdInError)?;
-        let mut line = String::new();
-        std::io::stdin().read_line(&mut line).map_err(|_| InterpreterError::StdInError)?;
-        trim_newline(&mut line);
+    });
+
+    add_native_function(&mut global_context, "input", |context, arguments| {
+        let line = read_input(&context, &arguments)?;
         Ok(Value::String(line))
     });
 
+    add_native_function(&mut global_context, "input_int", |context, arguments| {
+        let line = read_input(&context, &arguments)?;
+        line.trim().parse::<i32>()
+            .map(Value::Integer)
+            .map_err(|_| InterpreterError::ParseError(line).into())
+    });
+
+    add_native_function(&mut global_context, "input_float", |context, arguments| {
+        let line = read_input(&context, &arguments)?;
+        line.trim().parse::<f64>()
+            .map(Value::Float)
+            .map_err(|_| InterpreterError::ParseError(line).into())
+    });
+
+    add_native_function(&mut global_context, "read_file", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::String(path)] => fs::read_to_string(path)
+                .map(Value::String)
+                .map_err(|err| InterpreterError::IoError(format!("cannot read '{}': {}", path, err)).into()),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "write_file", |_context, arguments| {
+        match arguments.as_slice() {
+            [Value::String(path), Value::String(content)] => fs::write(path, content)
+                .map(|_| Value::Unit)
+                .map_err(|err| InterpreterError::IoError(format!("cannot write '{}': {}", path, err)).into()),
+            [_, _] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
+    add_native_function(&mut global_context, "exit", |_context, arguments| {
+        match arguments.as_slice() {
+            [] => Err(InterpreterError::Exit(0).into()),
+            [Value::Integer(code)] => Err(InterpreterError::Exit(*code).into()),
+            [_] => Err(InterpreterError::InvalidOperands.into()),
+            _ => Err(InterpreterError::WrongNumberOfArguments.into()),
+        }
+    });
+
     global_context
 }
 
+/// Substitutes each `{}` in the template with the next argument.
+/// There must be exactly as many arguments as placeholders.
+fn format_template(template: &str, arguments: &[Value]) -> Result<String, InterpreterErrorWithSpan> {
+    let mut output = String::new();
+    let mut arguments = arguments.iter();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                output.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                let argument = arguments.next().ok_or(InterpreterError::WrongNumberOfArguments)?;
+                output.push_str(&argument.to_string());
+            }
+            _ => output.push(c),
+        }
+    }
+    match arguments.is_empty() {
+        true => Ok(output),
+        false => Err(InterpreterError::WrongNumberOfArguments.into()),
+    }
+}
+
+fn as_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(x) => Some(*x as f64),
+        Value::Long(x) => Some(*x as f64),
+        Value::Float(x) => Some(*x),
+        _ => None,
+    }
+}
+
+/// Converts a float without a fractional part, failing where `as` would saturate.
+fn float_to_integer(x: f64, name: &str) -> Result<i32, InterpreterError> {
+    match x {
+        x if x.is_nan() => Err(InterpreterError::InvalidOperands),
+        x if x >= i32::MIN as f64 && x <= i32::MAX as f64 => Ok(x as i32),
+        _ => Err(InterpreterError::IntegerOverflow(name.to_owned())),
+    }
+}
+
+fn float_to_long(x: f64, name: &str) -> Result<i64, InterpreterError> {
+    match x {
+        x if x.is_nan() => Err(InterpreterError::InvalidOperands),
+        // i64::MAX isn't a float, it's rounded up to 2^63
+        x if x >= i64::MIN as f64 && x < i64::MAX as f64 => Ok(x as i64),
+        _ => Err(InterpreterError::IntegerOverflow(name.to_owned())),
+    }
+}
+
+/// Widens integers to longs.
+fn as_long(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(x) => Some(*x as i64),
+        Value::Long(x) => Some(*x),
+        _ => None,
+    }
+}
+
+/// Integer arithmetic where at least one operand is a long, the result is always a long.
+fn long_operation(lhs: &Value, rhs: &Value, operator: &str, operation: fn(i64, i64) -> Option<i64>) -> Result<Value, InterpreterErrorWithSpan> {
+    let (lhs, rhs) = (as_long(lhs).unwrap(), as_long(rhs).unwrap());
+    operation(lhs, rhs)
+        .map(Value::Long)
+        .ok_or_else(|| InterpreterError::IntegerOverflow(operator.to_owned()).into())
+}
+
+fn to_index(index: i32) -> Result<usize, InterpreterError> {
+    usize::try_from(index).map_err(|_| InterpreterError::InvalidIndex)
+}
+
+/// Resolves an index into a list of length `len`, where -1 is the last element.
+fn from_end_index(index: i32, len: usize) -> Result<usize, InterpreterError> {
+    let index = match index {
+        index if index < 0 => len as i64 + index as i64,
+        index => index as i64,
+    };
+    match usize::try_from(index) {
+        Ok(index) if index < len => Ok(index),
+        _ => Err(InterpreterError::IndexOutOfBounds),
+    }
+}
+
+pub(crate) fn negate(value: &Value) -> Result<Value, InterpreterError> {
+    match value {
+        Value::Integer(x) => x.checked_neg().map(Value::Integer).ok_or_else(|| InterpreterError::IntegerOverflow("-".to_owned())),
+        Value::Long(x) => x.checked_neg().map(Value::Long).ok_or_else(|| InterpreterError::IntegerOverflow("-".to_owned())),
+        Value::Float(x) => Ok(Value::Float(-x)),
+        _ => Err(InterpreterError::InvalidOperands),
+    }
+}
+
+/// Integers are widened to longs and converted to floats when compared with floats, so `(== 1 1.0)` is true.
+/// Strings and booleans are never converted to numbers, so `(== 1 "1")` is false.
+/// Lists are equal when they have the same length and their elements are equal.
+/// Maps are equal when they have the same keys with equal values, in any order.
+pub(crate) fn values_equal(lhs: &Value, rhs: &Value) -> bool {
+    use Value::*;
+    match (lhs, rhs) {
+        (Unit, Unit) => true,
+        (Boolean(x), Boolean(y)) => x == y,
+        (Integer(x), Integer(y)) => x == y,
+        (Integer(_) | Long(_), Integer(_) | Long(_)) => as_long(lhs) == as_long(rhs),
+        (Integer(x), Float(y)) => *x as f64 == *y,
+        (Long(x), Float(y)) => *x as f64 == *y,
+        (Float(x), Integer(y)) => *x == *y as f64,
+        (Float(x), Long(y)) => *x == *y as f64,
+        (Float(x), Float(y)) => x == y,
+        (String(x), String(y)) => x == y,
+        // A list containing itself would be compared forever
+        (List(x), List(y)) if Rc::ptr_eq(x, y) => true,
+        (Map(x), Map(y)) if Rc::ptr_eq(x, y) => true,
+        (List(x), List(y)) => {
+            let (x, y) = (x.borrow(), y.borrow());
+            x.len() == y.len() && x.iter().zip(y.iter()).all(|(x, y)| values_equal(x, y))
+        }
+        (Map(x), Map(y)) => {
+            // Keys are unique, so maps with the same length and the same pairs are equal
+            let (x, y) = (x.borrow(), y.borrow());
+            x.len() == y.len() && x.iter().all(|(key, value)| {
+                y.iter().any(|(k, v)| values_equal(key, k) && values_equal(value, v))
+            })
+        }
+        _ => false,
+    }
+}
+
+/// `()` has no order, comparing it with `<`, `>`, `<=` or `>=` is an error rather than `false`.
+fn check_orderable(arguments: &[Value]) -> Result<(), InterpreterErrorWithSpan> {
+    check_operands(arguments, |value| !matches!(value, Value::Unit))
+}
+
+/// Blames the first argument rejected by `is_valid`.
+fn check_operands(arguments: &[Value], is_valid: fn(&Value) -> bool) -> Result<(), InterpreterErrorWithSpan> {
+    match arguments.iter().position(|value| !is_valid(value)) {
+        Some(index) => Err(InterpreterError::InvalidOperands.at_argument(index)),
+        None => Ok(()),
+    }
+}
+
+fn is_number(value: &Value) -> bool {
+    matches!(value, Value::Integer(_) | Value::Long(_) | Value::Float(_))
+}
+
+fn is_number_or_string(value: &Value) -> bool {
+    matches!(value, Value::Integer(_) | Value::Long(_) | Value::Float(_) | Value::String(_))
+}
+
+/// Orders numbers, with the same conversions as `values_equal`, and strings.
+/// Any other combination can't be ordered.
+fn compare_values(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    use Value::*;
+    match (lhs, rhs) {
+        (Integer(x), Integer(y)) => x.partial_cmp(y),
+        (Integer(_) | Long(_), Integer(_) | Long(_)) => as_long(lhs).partial_cmp(&as_long(rhs)),
+        (Integer(x), Float(y)) => (*x as f64).partial_cmp(y),
+        (Long(x), Float(y)) => (*x as f64).partial_cmp(y),
+        (Float(x), Integer(y)) => x.partial_cmp(&(*y as f64)),
+        (Float(x), Long(y)) => x.partial_cmp(&(*y as f64)),
+        (Float(x), Float(y)) => x.partial_cmp(y),
+        (String(x), String(y)) => x.partial_cmp(y),
+        _ => None,
+    }
+}
+
+/// Keys are compared with the same rules as `==`, so setting an existing key replaces its value.
+fn insert_into_map(map: &mut Vec<(Value, Value)>, key: Value, value: Value) {
+    match map.iter_mut().find(|(k, _)| values_equal(k, &key)) {
+        Some((_, v)) => *v = value,
+        None => map.push((key, value)),
+    }
+}
+
+/// Runs `f` on the output of the context, the standard output if it has none.
+fn with_output<T>(context: &Rc<RefCell<Context>>, f: impl FnOnce(&mut dyn Write) -> io::Result<T>) -> Result<T, InterpreterError> {
+    let mut context = context.borrow_mut();
+    let result = match &mut context.output {
+        Some(output) => f(output.as_mut()),
+        None => f(&mut io::stdout()),
+    };
+    result.map_err(|_| InterpreterError::StdInError)
+}
+
+/// Runs `f` on the input of the context, the standard input if it has none.
+fn with_input<T>(context: &Rc<RefCell<Context>>, f: impl FnOnce(&mut dyn BufRead) -> io::Result<T>) -> Result<T, InterpreterError> {
+    let mut context = context.borrow_mut();
+    let result = match &mut context.input {
+        Some(input) => f(input.as_mut()),
+        None => f(&mut io::stdin().lock()),
+    };
+    result.map_err(|_| InterpreterError::StdInError)
+}
+
+/// Prints the optional prompt, then reads a line without its line terminator.
+fn read_input(context: &Rc<RefCell<Context>>, arguments: &[Value]) -> Result<String, InterpreterError> {
+    match arguments {
+        [] => (),
+        [to_print] => with_output(context, |output| write!(output, "{}", to_print))?,
+        _ => return Err(InterpreterError::WrongNumberOfArguments)
+    }
+    with_output(context, |output| output.flush())?;
+    let mut line = String::new();
+    with_input(context, |input| input.read_line(&mut line))?;
+    trim_newline(&mut line);
+    Ok(line)
+}
+
 fn trim_newline(s: &mut String) {
     if s.ends_with('\n') {
         s.pop();
@@ -287,4 +1116,517 @@ fn trim_newline(s: &mut String) {
             s.pop();
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{run, Lexer, Parser, RunError};
+
+    use super::*;
+
+    /// Collects what the program writes, it can still be read after the context has taken ownership.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Evaluates `source` reading `input` instead of the standard input, returns what was printed.
+    fn eval_with_input(source: &str, input: &str) -> String {
+        let (result, printed) = try_eval_with_input(source, input);
+        result.unwrap();
+        printed
+    }
+
+    fn try_eval_with_input(source: &str, input: &str) -> (Result<Value, InterpreterErrorWithSpan>, String) {
+        let output = SharedBuffer::default();
+        let global_context = create_global_context_with_native_functions();
+        global_context.borrow_mut().input = Some(Box::new(Cursor::new(input.to_owned())));
+        global_context.borrow_mut().output = Some(Box::new(output.clone()));
+
+        let (tokens, indices) = Lexer::new(source).tokenize().unwrap();
+        let expressions = Parser::new((tokens.as_slice(), indices.as_slice())).parse().unwrap();
+        let result = expressions.iter().try_fold(Value::Unit, |_, expression| expression.evaluate(global_context.clone()));
+        let printed = output.0.borrow().clone();
+        (result, String::from_utf8(printed).unwrap())
+    }
+
+    fn eval(source: &str) -> Value {
+        run(source).unwrap()
+    }
+
+    fn eval_error(source: &str) -> InterpreterError {
+        match run(source) {
+            Err(RunError::InterpreterError(err)) => err.error,
+            result => panic!("expected an interpreter error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn map_insert_and_retrieve() {
+        assert!(matches!(eval(r#"let m = (dict "a" 1 "b" 2) (set m "c" 3) (get m "c")"#), Value::Integer(3)));
+        assert!(matches!(eval(r#"let m = (dict "a" 1) (set m "a" 5) (get m "a")"#), Value::Integer(5)));
+        assert_eq!(eval(r#"(keys (dict "a" 1 "b" 2))"#).to_string(), "[a b]");
+        assert!(matches!(eval_error(r#"(get (dict "a" 1) "b")"#), InterpreterError::KeyNotFound));
+    }
+
+    #[test]
+    fn maps_are_equal_structurally() {
+        assert!(matches!(eval(r#"let m = (dict "a" 1) (== m m)"#), Value::Boolean(true)));
+        assert!(matches!(eval(r#"(== (dict "a" 1 "b" 2) (dict "b" 2 "a" 1))"#), Value::Boolean(true)));
+        assert!(matches!(eval(r#"(== (dict "a" 1) (dict "a" 2))"#), Value::Boolean(false)));
+        assert!(matches!(eval(r#"(== (dict "a" 1) (dict "a" 1 "b" 2))"#), Value::Boolean(false)));
+    }
+
+    #[test]
+    fn map_applies_a_function_to_each_element() {
+        assert_eq!(eval("(map fn (x) (* x 2) [1 2 3])").to_string(), "[2 4 6]");
+        assert!(matches!(eval_error("(map 5 [1 2])"), InterpreterError::InvalidOperands));
+        assert!(matches!(eval_error("(map fn (x) x 5)"), InterpreterError::InvalidOperands));
+    }
+
+    #[test]
+    fn filter_and_reduce() {
+        assert_eq!(eval("(filter fn (x) (> x 1) [1 2 3])").to_string(), "[2 3]");
+        assert!(matches!(eval("(reduce fn (acc x) (+ acc x) 0 [1 2 3])"), Value::Integer(6)));
+        assert!(matches!(eval_error("(filter [1] [1 2])"), InterpreterError::InvalidOperands));
+    }
+
+    #[test]
+    fn math_functions() {
+        assert!(matches!(eval("(abs -3)"), Value::Integer(3)));
+        assert!(matches!(eval("(abs -2.5)"), Value::Float(x) if x == 2.5));
+        assert!(matches!(eval("(sqrt 16)"), Value::Float(x) if x == 4.0));
+        assert!(matches!(eval("(floor 2.7)"), Value::Integer(2)));
+        assert!(matches!(eval("(ceil 2.1)"), Value::Integer(3)));
+        assert!(matches!(eval("(round -2.5)"), Value::Integer(-3)));
+        assert!(matches!(eval("(min 3 1.5 2)"), Value::Float(x) if x == 1.5));
+        assert!(matches!(eval("(max 3 1.5 2)"), Value::Integer(3)));
+        assert!(matches!(eval_error(r#"(sqrt "4")"#), InterpreterError::InvalidOperands));
+    }
+
+    #[test]
+    fn rounding_out_of_range_is_an_error() {
+        assert!(matches!(eval_error("(floor 100000000000.5)"), InterpreterError::IntegerOverflow(_)));
+        assert!(matches!(eval_error("(round (/ 1.0 0.0))"), InterpreterError::IntegerOverflow(_)));
+        assert!(matches!(eval_error("(round (/ 0.0 0.0))"), InterpreterError::InvalidOperands));
+        assert!(matches!(eval("(ceil -2147483648.5)"), Value::Integer(i32::MIN)));
+    }
+
+    #[test]
+    fn conversions() {
+        assert!(matches!(eval(r#"(to_int " 42 ")"#), Value::Integer(42)));
+        assert!(matches!(eval("(to_int -3.9)"), Value::Integer(-3)));
+        assert!(matches!(eval("(to_float 2)"), Value::Float(x) if x == 2.0));
+        assert!(matches!(eval(r#"(to_float "2.5")"#), Value::Float(x) if x == 2.5));
+        assert!(matches!(eval("(to_long 100000000000.5)"), Value::Long(100000000000)));
+        assert!(matches!(eval("(to_string [1 2.5])"), Value::String(s) if s == "[1 2.5]"));
+        assert!(matches!(eval_error(r#"(to_int "forty")"#), InterpreterError::ParseError(s) if s == "forty"));
+    }
+
+    #[test]
+    fn converting_out_of_range_floats_is_an_error() {
+        assert!(matches!(eval_error("(to_int 100000000000.5)"), InterpreterError::IntegerOverflow(_)));
+        assert!(matches!(eval_error("(to_int (/ 0.0 0.0))"), InterpreterError::InvalidOperands));
+        assert!(matches!(eval_error("(to_long 9223372036854775808.0)"), InterpreterError::IntegerOverflow(_)));
+        assert!(matches!(eval_error("(to_long (/ -1.0 0.0))"), InterpreterError::IntegerOverflow(_)));
+    }
+
+    #[test]
+    fn println_writes_to_the_output_of_the_context() {
+        assert_eq!(eval_with_input(r#"(print "a") (print 1) (println [2.5 "b"]) (println)"#, ""), "a1[2.5 b]\n\n");
+    }
+
+    #[test]
+    fn input_reads_from_the_input_of_the_context() {
+        let source = r#"
+            let name = (input "name? ")
+            let age = (input_int)
+            (println (format "{} is {}" name (+ age 1)))
+        "#;
+        assert_eq!(eval_with_input(source, "Ada\r\n35\n"), "name? Ada is 36\n");
+    }
+
+    #[test]
+    fn integers_widen_to_longs() {
+        assert!(matches!(eval("(* 1000000 1000000L)"), Value::Long(1000000000000)));
+        assert!(matches!(eval("(+ 2147483647 1L)"), Value::Long(2147483648)));
+        assert!(matches!(eval("(typeof (- 5L 2))"), Value::String(s) if s == "long"));
+        assert!(matches!(eval("(== 3L 3)"), Value::Boolean(true)));
+        assert!(matches!(eval("(< 2 3L 4.5)"), Value::Boolean(true)));
+        assert!(matches!(eval_error("(* 1000000 1000000)"), InterpreterError::IntegerOverflow(_)));
+        assert!(matches!(eval_error("(* 9223372036854775807L 2)"), InterpreterError::IntegerOverflow(_)));
+    }
+
+    #[test]
+    fn modulo() {
+        assert!(matches!(eval("(% 10 3)"), Value::Integer(1)));
+        assert!(matches!(eval("(% -7 3)"), Value::Integer(-1)));
+        assert!(matches!(eval("(% 7.5 2)"), Value::Float(x) if x == 1.5));
+        assert!(matches!(eval("(% 100 7 3)"), Value::Integer(2)));
+        assert!(matches!(eval_error("(% 10 0)"), InterpreterError::DivisionByZero));
+        assert!(matches!(eval_error(r#"(% 10 "3")"#), InterpreterError::InvalidOperands));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        match run("(/ 5 0)") {
+            Err(RunError::InterpreterError(err)) => {
+                assert!(matches!(err.error, InterpreterError::DivisionByZero));
+                assert_eq!(err.span, Some(0..7));
+            }
+            result => panic!("expected a division by zero, got {:?}", result),
+        }
+        assert!(matches!(eval("(/ 5.0 0.0)"), Value::Float(x) if x == f64::INFINITY));
+        assert!(matches!(eval("(/ 0.0 0.0)"), Value::Float(x) if x.is_nan()));
+        assert!(matches!(eval("(/ 7 2)"), Value::Integer(3)));
+    }
+
+    #[test]
+    fn integers_and_floats_compare_by_value() {
+        assert!(matches!(eval("(== 1 1.0)"), Value::Boolean(true)));
+        assert!(matches!(eval("(!= 1 1.5)"), Value::Boolean(true)));
+        assert!(matches!(eval("(< 1 1.5 2)"), Value::Boolean(true)));
+        assert!(matches!(eval("(>= 2.0 2 1.5)"), Value::Boolean(true)));
+        assert!(matches!(eval("(> 1 1.0)"), Value::Boolean(false)));
+    }
+
+    #[test]
+    fn strings_and_booleans_are_never_numbers() {
+        assert!(matches!(eval(r#"(== 1 "1")"#), Value::Boolean(false)));
+        assert!(matches!(eval("(== 1 true)"), Value::Boolean(false)));
+        assert!(matches!(eval(r#"(!= 1 "1")"#), Value::Boolean(true)));
+        assert!(matches!(eval(r#"(< 1 "2")"#), Value::Boolean(false)));
+    }
+
+    #[test]
+    fn lists_are_equal_structurally() {
+        assert!(matches!(eval("(== [1 [2 3] []] [1 [2.0 3] []])"), Value::Boolean(true)));
+        assert!(matches!(eval("(== [1 [2 3]] [1 [2 4]])"), Value::Boolean(false)));
+        assert!(matches!(eval("(!= [1 [2 3]] [1 [2 4]])"), Value::Boolean(true)));
+        assert!(matches!(eval("(== [1 2] [1 2 3])"), Value::Boolean(false)));
+        assert!(matches!(eval(r#"(== [1] ["1"])"#), Value::Boolean(false)));
+    }
+
+    #[test]
+    fn pop_remove_and_insert() {
+        assert_eq!(eval("let l = [1 2 3] [(pop l) l]").to_string(), "[3 [1 2]]");
+        assert!(matches!(eval_error("(pop [])"), InterpreterError::IndexOutOfBounds));
+
+        assert_eq!(eval("let l = [1 2 3] [(remove l 1) l]").to_string(), "[2 [1 3]]");
+        assert!(matches!(eval_error("(remove [1 2 3] 3)"), InterpreterError::IndexOutOfBounds));
+
+        assert_eq!(eval("let l = [1 2] (insert l 0 0) l").to_string(), "[0 1 2]");
+        assert_eq!(eval("let l = [1 2] (insert l 2 3) l").to_string(), "[1 2 3]");
+        assert!(matches!(eval_error("(insert [1 2] 3 0)"), InterpreterError::IndexOutOfBounds));
+    }
+
+    #[test]
+    fn format_substitutes_placeholders() {
+        assert!(matches!(eval(r#"(format "{} + {} = {}" 1 2.5 [3])"#), Value::String(s) if s == "1 + 2.5 = [3]"));
+        assert!(matches!(eval(r#"(format "{{}} {{{}}}" "x")"#), Value::String(s) if s == "{} {x}"));
+        assert!(matches!(eval(r#"(format "no placeholders")"#), Value::String(s) if s == "no placeholders"));
+        assert!(matches!(eval_error(r#"(format "{} {}" 1)"#), InterpreterError::WrongNumberOfArguments));
+        assert!(matches!(eval_error(r#"(format "{}" 1 2)"#), InterpreterError::WrongNumberOfArguments));
+    }
+
+    #[test]
+    fn power_keeps_integers() {
+        assert!(matches!(eval("(** 2 10)"), Value::Integer(1024)));
+        assert!(matches!(eval("(** 2 -1)"), Value::Float(x) if x == 0.5));
+        assert!(matches!(eval("(** 4 0.5)"), Value::Float(x) if x == 2.0));
+        assert!(matches!(eval_error("(** 2 31)"), InterpreterError::IntegerOverflow(_)));
+    }
+
+    #[test]
+    fn floor_div_rounds_towards_negative_infinity() {
+        assert!(matches!(eval("(floor_div 7 2)"), Value::Integer(3)));
+        assert!(matches!(eval("(floor_div -7 2)"), Value::Integer(-4)));
+        assert!(matches!(eval("(floor_div 7 -2)"), Value::Integer(-4)));
+        assert!(matches!(eval("(floor_div -8 2)"), Value::Integer(-4)));
+        assert!(matches!(eval("(floor_div 7.5 2)"), Value::Float(x) if x == 3.0));
+        assert!(matches!(eval_error("(floor_div 1 0)"), InterpreterError::DivisionByZero));
+    }
+
+    #[test]
+    fn integer_overflow_is_an_error() {
+        assert!(matches!(eval_error("(+ 2147483647 1)"), InterpreterError::IntegerOverflow(op) if op == "+"));
+        assert!(matches!(eval_error("(- -2147483648 1)"), InterpreterError::IntegerOverflow(op) if op == "-"));
+        assert!(matches!(eval_error("(* 65536 65536)"), InterpreterError::IntegerOverflow(op) if op == "*"));
+        assert!(matches!(eval_error("(/ -2147483648 -1)"), InterpreterError::IntegerOverflow(op) if op == "/"));
+        assert!(matches!(eval_error("(abs -2147483648)"), InterpreterError::IntegerOverflow(op) if op == "abs"));
+        // Floats are unaffected
+        assert!(matches!(eval("(+ 2147483647 1.0)"), Value::Float(x) if x == 2147483648.0));
+    }
+
+    #[test]
+    fn unit_equality_and_ordering() {
+        assert!(matches!(eval("(== () ())"), Value::Boolean(true)));
+        assert!(matches!(eval("(!= () 0)"), Value::Boolean(true)));
+        assert_eq!(eval("[()]").to_string(), "[()]");
+        assert!(matches!(eval_error("(< () 1)"), InterpreterError::InvalidOperands));
+        assert!(matches!(eval_error("(>= 1 ())"), InterpreterError::InvalidOperands));
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        assert!(matches!(eval("(get [1 2 3] -1)"), Value::Integer(3)));
+        assert!(matches!(eval("(get [1 2 3] -3)"), Value::Integer(1)));
+        assert_eq!(eval("let l = [1 2 3] (set l -2 5) l").to_string(), "[1 5 3]");
+        assert!(matches!(eval_error("(get [1 2 3] -4)"), InterpreterError::IndexOutOfBounds));
+        assert!(matches!(eval_error("(set [1 2 3] -4 0)"), InterpreterError::IndexOutOfBounds));
+        assert!(matches!(eval_error("(get [] -1)"), InterpreterError::IndexOutOfBounds));
+    }
+
+    #[test]
+    fn float_precision() {
+        assert_eq!(eval("(+ 0.1 0.2)").to_string(), "0.30000000000000004");
+        assert!(matches!(eval("(to_string 3.14159 2)"), Value::String(s) if s == "3.14"));
+        assert!(matches!(eval("(to_string 2.5 0)"), Value::String(s) if s == "2"));
+        assert!(matches!(eval("(to_string 1.0 3)"), Value::String(s) if s == "1.000"));
+        assert!(matches!(eval_error("(to_string 1.0 -1)"), InterpreterError::InvalidOperands));
+
+        crate::set_float_precision(Some(2));
+        let printed = eval("[(+ 0.1 0.2) 1.0 3]").to_string();
+        crate::set_float_precision(None);
+        assert_eq!(printed, "[0.30 1.00 3]");
+    }
+
+    #[test]
+    fn dbg_takes_one_argument() {
+        assert_eq!(eval_with_input("(dbg 1)", ""), "Integer(\n    1,\n)\n");
+        assert!(matches!(eval_error("(dbg)"), InterpreterError::WrongNumberOfArguments));
+        assert!(matches!(eval_error("(dbg 1 2)"), InterpreterError::WrongNumberOfArguments));
+    }
+
+    #[test]
+    fn lists_containing_themselves_are_equal_to_themselves() {
+        assert!(matches!(eval("let l = [1 2] (push l l) (== l l)"), Value::Boolean(true)));
+        assert!(matches!(eval("let l = [1 2] (push l l) (!= l l)"), Value::Boolean(false)));
+        assert!(matches!(eval("let l = [1 2] (push l l) (== l [1 2])"), Value::Boolean(false)));
+    }
+
+    #[test]
+    fn maps_and_lists_can_contain_themselves() {
+        assert_eq!(eval("let m = (dict (dict) 1) (set m m 2) (len (keys m))").to_string(), "2");
+        assert_eq!(eval("let m = (dict) (set m m 1) (set m m 2) (values m)").to_string(), "[2]");
+        assert_eq!(eval("let m = (dict) (set m 1 m) m").to_string(), "{1: {...}}");
+        assert_eq!(eval_with_input("let l = [1 2] (push l l) (println l)", ""), "[1 2 [...]]\n");
+        assert!(matches!(eval("let l = [1 2] (push l l) (== l l)"), Value::Boolean(true)));
+    }
+
+    #[test]
+    fn typeof_names_each_kind_of_value() {
+        let type_of = |source| eval(source).to_string();
+        assert_eq!(type_of("(typeof 1)"), "integer");
+        assert_eq!(type_of("(typeof 1L)"), "long");
+        assert_eq!(type_of("(typeof 1.5)"), "float");
+        assert_eq!(type_of(r#"(typeof "a")"#), "string");
+        assert_eq!(type_of("(typeof true)"), "boolean");
+        assert_eq!(type_of("(typeof ())"), "unit");
+        assert_eq!(type_of("(typeof [1 2])"), "list");
+        assert_eq!(type_of("(typeof (dict))"), "map");
+        assert_eq!(type_of("(typeof typeof)"), "function");
+        assert_eq!(type_of("(typeof fn (x) x)"), "function");
+        assert!(matches!(eval_error("(typeof)"), InterpreterError::WrongNumberOfArguments));
+        assert!(matches!(eval_error("(typeof 1 2)"), InterpreterError::WrongNumberOfArguments));
+    }
+
+    #[test]
+    fn assert_fails_with_its_message_at_the_call() {
+        assert!(matches!(eval("let x = 5 (assert (== x 5))"), Value::Unit));
+        assert!(matches!(eval(r#"(assert true "never shown")"#), Value::Unit));
+
+        let source = r#"let x = 4 (assert (== x 5) "x must be five")"#;
+        match run(source) {
+            Err(RunError::InterpreterError(error)) => {
+                assert!(matches!(&error.error, InterpreterError::AssertionFailed(message) if message == "x must be five"), "{:?}", error);
+                assert_eq!(error.span.map(|span| &source[span]), Some(r#"(assert (== x 5) "x must be five")"#));
+            }
+            result => panic!("expected an assertion failure, got {:?}", result),
+        }
+        assert!(matches!(eval_error("(assert false)"), InterpreterError::AssertionFailed(message) if message == "assertion failed"));
+        assert!(matches!(eval_error("(assert 1)"), InterpreterError::InvalidOperands));
+    }
+
+    #[test]
+    fn string_functions() {
+        let string = |source| eval(source).to_string();
+        assert_eq!(string(r#"(to_upper "héllo")"#), "HÉLLO");
+        assert_eq!(string(r#"(to_lower "HÉLLO")"#), "héllo");
+        assert_eq!(string("(trim \"  a b \n\")"), "a b");
+        assert!(matches!(eval(r#"(contains "héllo" "él")"#), Value::Boolean(true)));
+        assert!(matches!(eval(r#"(contains "héllo" "el")"#), Value::Boolean(false)));
+        assert!(matches!(eval(r#"(contains "" "")"#), Value::Boolean(true)));
+        assert_eq!(string(r#"(replace "a-b-c" "-" "+")"#), "a+b+c");
+        assert_eq!(string(r#"(replace "abc" "x" "y")"#), "abc");
+        assert!(matches!(eval_error(r#"(to_upper 1)"#), InterpreterError::InvalidOperands));
+        assert!(matches!(eval_error(r#"(replace "a" 1 "b")"#), InterpreterError::InvalidOperands));
+        assert!(matches!(eval_error(r#"(trim "a" "b")"#), InterpreterError::WrongNumberOfArguments));
+    }
+
+    #[test]
+    fn substring_counts_characters_and_checks_its_bounds() {
+        let string = |source| eval(source).to_string();
+        assert_eq!(string(r#"(substring "héllo" 1 3)"#), "él");
+        assert_eq!(string(r#"(substring "héllo" 0 5)"#), "héllo");
+        assert_eq!(string(r#"(substring "héllo" 5 5)"#), "");
+        assert_eq!(string(r#"(substring "日本語" 2 3)"#), "語");
+        assert!(matches!(eval_error(r#"(substring "héllo" 0 6)"#), InterpreterError::IndexOutOfBounds));
+        assert!(matches!(eval_error(r#"(substring "héllo" 6 6)"#), InterpreterError::IndexOutOfBounds));
+        assert!(matches!(eval_error(r#"(substring "héllo" 3 1)"#), InterpreterError::IndexOutOfBounds));
+        assert!(matches!(eval_error(r#"(substring "héllo" -1 2)"#), InterpreterError::InvalidIndex));
+    }
+
+    #[test]
+    fn split_and_join() {
+        let string = |source| eval(source).to_string();
+        assert_eq!(string(r#"(split "a,b,c" ",")"#), "[a b c]");
+        assert_eq!(string(r#"(len (split "a,,b" ","))"#), "3");
+        assert_eq!(string(r#"(split "héj" "")"#), "[h é j]");
+        assert_eq!(string(r#"(len (split "" ","))"#), "1");
+        assert_eq!(string(r#"(len (split "" ""))"#), "0");
+        assert_eq!(string(r#"(join [1 "b" true] "-")"#), "1-b-true");
+        assert_eq!(string(r#"(join [] ",")"#), "");
+        for (text, separator) in [("a,b,c", ","), ("", ","), ("a,,b,", ","), ("héllo", ""), ("a--b", "--")] {
+            let source = format!(r#"(join (split "{0}" "{1}") "{1}")"#, text, separator);
+            assert_eq!(eval(&source).to_string(), text, "{}", source);
+        }
+        assert!(matches!(eval_error(r#"(split "a" 1)"#), InterpreterError::InvalidOperands));
+        assert!(matches!(eval_error(r#"(join "a" ",")"#), InterpreterError::InvalidOperands));
+    }
+
+    #[test]
+    fn list_functions_on_empty_and_non_empty_lists() {
+        let string = |source| eval(source).to_string();
+        assert_eq!(string("(len [])"), "0");
+        assert_eq!(string("(len [1 [2 3]])"), "2");
+        assert_eq!(string("(reverse [])"), "[]");
+        assert_eq!(string("(reverse [1 2 3])"), "[3 2 1]");
+        assert_eq!(string("let l = [1 2] (reverse l) l"), "[1 2]");
+        assert!(matches!(eval("(contains [] 1)"), Value::Boolean(false)));
+        assert!(matches!(eval("(contains [1 [2] 3] [2])"), Value::Boolean(true)));
+        assert!(matches!(eval("(contains [1 2] 2.0)"), Value::Boolean(true)));
+        assert!(matches!(eval(r#"(contains [1 2] "2")"#), Value::Boolean(false)));
+        assert_eq!(string("(slice [] 0 0)"), "[]");
+        assert_eq!(string("(slice [] 1 5)"), "[]");
+        assert_eq!(string("(slice [1 2 3 4] 1 3)"), "[2 3]");
+        assert_eq!(string("(slice [1 2 3 4] 2 10)"), "[3 4]");
+        assert_eq!(string("(slice [1 2 3 4] 3 1)"), "[]");
+        assert!(matches!(eval_error("(slice [1 2] -1 1)"), InterpreterError::InvalidIndex));
+        assert!(matches!(eval_error("(reverse 1)"), InterpreterError::InvalidOperands));
+        assert!(matches!(eval_error("(len 1)"), InterpreterError::InvalidOperands));
+    }
+
+    #[test]
+    fn range_forms() {
+        let string = |source| eval(source).to_string();
+        assert_eq!(string("(range 4)"), "[0 1 2 3]");
+        assert_eq!(string("(range 2 5)"), "[2 3 4]");
+        assert_eq!(string("(range 0 10 3)"), "[0 3 6 9]");
+        assert_eq!(string("(range 0)"), "[]");
+        assert_eq!(string("(range -2)"), "[]");
+        assert_eq!(string("(range 5 5)"), "[]");
+        assert_eq!(string("(range 5 2)"), "[]");
+        assert_eq!(string("(range 5 5 -1)"), "[]");
+        assert_eq!(string("(range 5 0 -2)"), "[5 3 1]");
+        assert!(matches!(eval_error("(range 0 5 0)"), InterpreterError::InvalidOperands));
+        assert!(matches!(eval_error("(range 0 5 -1)"), InterpreterError::InvalidOperands));
+        assert!(matches!(eval_error("(range 5 0 1)"), InterpreterError::InvalidOperands));
+        assert!(matches!(eval_error("(range 1.5)"), InterpreterError::InvalidOperands));
+        assert!(matches!(eval_error("(range)"), InterpreterError::WrongNumberOfArguments));
+    }
+
+    #[test]
+    fn write_then_read_a_file() {
+        let directory = std::env::temp_dir().join(format!("rusp-files-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("notes.txt");
+        let path = path.display();
+
+        let source = format!(r#"(write_file "{0}" "héllo
+world") (read_file "{0}")"#, path);
+        assert_eq!(eval(&source).to_string(), "héllo\nworld");
+        let source = format!(r#"(write_file "{0}" "") (read_file "{0}")"#, path);
+        assert_eq!(eval(&source).to_string(), "");
+
+        let source = format!(r#"(read_file "{}")"#, directory.join("missing.txt").display());
+        assert!(matches!(eval_error(&source), InterpreterError::IoError(message) if message.contains("missing.txt")));
+        let source = format!(r#"(write_file "{}" "x")"#, directory.join("missing/notes.txt").display());
+        assert!(matches!(eval_error(&source), InterpreterError::IoError(_)));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn len_counts_characters_not_bytes() {
+        assert!(matches!(eval(r#"(len "héllo")"#), Value::Integer(5)));
+        assert!(matches!(eval(r#"(len "日本語")"#), Value::Integer(3)));
+        assert!(matches!(eval(r#"(len "")"#), Value::Integer(0)));
+        assert!(matches!(eval(r#"(len ["héllo" "日本語"])"#), Value::Integer(2)));
+    }
+
+    #[test]
+    fn comparisons_chain() {
+        assert!(matches!(eval("(< 1 2 3)"), Value::Boolean(true)));
+        assert!(matches!(eval("(< 1 3 2)"), Value::Boolean(false)));
+        assert!(matches!(eval("(< 1 2.0 3)"), Value::Boolean(true)));
+        assert!(matches!(eval("(< 1 2.5 2)"), Value::Boolean(false)));
+        assert!(matches!(eval("(<= 1 1L 1.0 2)"), Value::Boolean(true)));
+        assert!(matches!(eval("(> 3 2 2)"), Value::Boolean(false)));
+        assert!(matches!(eval("(== 1 1.0 1L)"), Value::Boolean(true)));
+        for operator in ["<", ">", "<=", ">=", "=="] {
+            assert!(matches!(eval(&format!("({})", operator)), Value::Boolean(true)), "{}", operator);
+            assert!(matches!(eval(&format!("({} 1)", operator)), Value::Boolean(true)), "{}", operator);
+        }
+    }
+
+    #[test]
+    fn typed_input_parses_the_trimmed_line() {
+        let (result, printed) = try_eval_with_input(r#"(input_float "x? ")"#, " 2.5 \n");
+        assert!(matches!(result, Ok(Value::Float(x)) if x == 2.5));
+        assert_eq!(printed, "x? ");
+        assert!(matches!(try_eval_with_input("(input_float)", "3\n").0, Ok(Value::Float(x)) if x == 3.0));
+        assert!(matches!(try_eval_with_input("(input_int)", "-7\n").0, Ok(Value::Integer(-7))));
+
+        let (result, printed) = try_eval_with_input(r#"(input_int "n? ")"#, "seven\n");
+        assert!(matches!(result, Err(e) if matches!(&e.error, InterpreterError::ParseError(line) if line == "seven")));
+        assert_eq!(printed, "n? ");
+        assert!(matches!(try_eval_with_input("(input_int)", "2.5\n").0, Err(e) if matches!(e.error, InterpreterError::ParseError(_))));
+        assert!(matches!(try_eval_with_input("(input_float)", "two\n").0, Err(e) if matches!(&e.error, InterpreterError::ParseError(line) if line == "two")));
+    }
+
+    #[test]
+    fn exit_unwinds_nested_calls() {
+        let source = r#"
+            fn inner () { (println "inner") (exit 4) (println "after exit") }
+            fn outer () { (inner) (println "after inner") }
+            (outer)
+            (println "after outer")
+        "#;
+        let (result, printed) = try_eval_with_input(source, "");
+        assert!(matches!(result, Err(e) if matches!(e.error, InterpreterError::Exit(4))));
+        assert_eq!(printed, "inner\n");
+        assert!(matches!(eval_error("while true { (exit) }"), InterpreterError::Exit(0)));
+        assert!(matches!(eval_error("(exit 1.5)"), InterpreterError::InvalidOperands));
+    }
+
+    #[test]
+    fn set_replaces_an_element_in_place() {
+        assert_eq!(eval("let l = [1 2 3] (set l 0 7) (set l 2 [9]) l").to_string(), "[7 2 [9]]");
+        assert!(matches!(eval("let l = [1 2 3] (set l 1 5) (get l 1)"), Value::Integer(5)));
+        // The list is shared, setting an element is visible through every variable holding it
+        assert_eq!(eval("let l = [1 2] let m = l (set m 1 3) l").to_string(), "[1 3]");
+        // The slot is replaced, a previously read element keeps its value
+        assert!(matches!(eval("let l = [1 2] let first = (get l 0) (set l 0 5) first"), Value::Integer(1)));
+        assert!(matches!(eval_error("(set [1 2 3] 3 0)"), InterpreterError::IndexOutOfBounds));
+        assert!(matches!(eval_error("(set [] 0 0)"), InterpreterError::IndexOutOfBounds));
+    }
+}
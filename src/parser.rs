@@ -2,7 +2,9 @@
 /// It outputs a vector of Expressions to be evaluated by the interpreter.
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::ops::Range;
+use std::fmt::{Debug, Formatter};
+use std::io::{BufRead, Write};
+use std::ops::{Range, RangeInclusive};
 use std::rc::Rc;
 
 use ParserError::*;
@@ -10,11 +12,25 @@ use ParserError::*;
 use crate::interpreter::InterpreterErrorWithSpan;
 use crate::lexer::{Keyword, Literal, Token};
 use crate::parser::Expression::Scope;
+use crate::symbol::Symbol;
 
-#[derive(Default, Debug)]
+#[derive(Default)]
 pub struct Context {
     pub parent_context: Option<Rc<RefCell<Context>>>,
-    pub variables: HashMap<String, Value>,
+    pub variables: HashMap<Symbol, Value>,
+    /// Where the native I/O functions read and write, the standard input and output when `None`.
+    /// Only the global context's handles are used.
+    pub input: Option<Box<dyn BufRead>>,
+    pub output: Option<Box<dyn Write>>,
+}
+
+impl Debug for Context {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Context")
+            .field("parent_context", &self.parent_context)
+            .field("variables", &self.variables)
+            .finish()
+    }
 }
 
 impl Context {
@@ -27,24 +43,28 @@ impl Context {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExpressionWithMetadata {
     pub expression: Expression,
     pub span: Range<usize>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Label {
-    pub label: String,
+    pub label: Symbol,
     pub span: Range<usize>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expression {
-    Id(String),
+    Id(Symbol),
     Value(Value),
     Declaration(Label, Box<ExpressionWithMetadata>),
     Assignment(Label, Box<ExpressionWithMetadata>),
     Scope(Vec<ExpressionWithMetadata>),
+    List(Vec<ExpressionWithMetadata>),
     NamedFunctionDefinition {
         name: Label,
         parameters: Vec<Label>,
@@ -68,15 +88,59 @@ pub enum Expression {
         guard: Box<ExpressionWithMetadata>,
         body: Box<ExpressionWithMetadata>,
     },
+    DoWhile {
+        body: Box<ExpressionWithMetadata>,
+        guard: Box<ExpressionWithMetadata>,
+    },
+    Match {
+        scrutinee: Box<ExpressionWithMetadata>,
+        arms: Vec<MatchArm>,
+    },
+    UnaryOp(UnaryOperator, Box<ExpressionWithMetadata>),
+    /// `import "path"`, the path is relative to the importing file
+    Import(String),
+    Break,
+    Continue,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnaryOperator {
+    /// `(- x)`
+    Negate,
+    /// `!x`
+    Not,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: ExpressionWithMetadata,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Pattern {
+    /// Matches values equal to this one, with the same semantics as `==`
+    Value(Value),
+    /// `_`, matches anything
+    Wildcard,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
     Unit,
     Integer(i32),
-    Float(f32),
+    Long(i64),
+    Float(f64),
     String(String),
     Boolean(bool),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Vec<(Value, Value)>>>),
+    // Functions hold their context, so they can't be serialized
+    #[cfg_attr(feature = "serde", serde(skip))]
     Function(Function),
 }
 
@@ -84,6 +148,11 @@ pub enum Value {
 pub enum ParserError {
     UnexpectedToken(Range<usize>),
     UnexpectedEOF,
+    DuplicateParameter(Range<usize>),
+    DanglingElse(Range<usize>),
+    BreakOutsideLoop(Range<usize>),
+    ContinueOutsideLoop(Range<usize>),
+    WrongOperatorArity { span: Range<usize>, operator: String },
 }
 
 #[derive(Debug, Clone)]
@@ -96,16 +165,38 @@ pub enum Function {
     RuspFunction {
         closing_context: Rc<RefCell<Context>>,
         name: String,
-        parameters: Vec<String>,
+        parameters: Vec<Symbol>,
         body: Box<ExpressionWithMetadata>,
     },
 }
 
+/// The `Debug` representation of each top-level expression, which only contains literal values,
+/// so it doesn't depend on the memory layout and can be compared across runs.
+pub fn dump_ast(expressions: &[ExpressionWithMetadata]) -> String {
+    let mut dump = String::new();
+    for expression in expressions {
+        dump += &format!("{:#?}\n", expression);
+    }
+    dump
+}
+
+#[cfg(feature = "serde")]
+pub fn ast_to_json(expressions: &[ExpressionWithMetadata]) -> serde_json::Result<String> {
+    serde_json::to_string(expressions)
+}
+
+#[cfg(feature = "serde")]
+pub fn ast_from_json(json: &str) -> serde_json::Result<Vec<ExpressionWithMetadata>> {
+    serde_json::from_str(json)
+}
+
 pub struct Parser<'a> {
     tokens: &'a [Token],
     token_indices: &'a [Range<usize>],
     utf8_start_index: usize,
     utf8_end_index: usize,
+    // Number of while bodies around the current expression, within the current function
+    loop_depth: usize,
 }
 
 impl<'a> Parser<'a> {
@@ -115,6 +206,7 @@ impl<'a> Parser<'a> {
             token_indices: indices,
             utf8_start_index: indices.first().map_or(0, |r| r.start),
             utf8_end_index: indices.first().map_or(0, |r| r.end),
+            loop_depth: 0,
         }
     }
 
@@ -146,12 +238,13 @@ impl<'a> Parser<'a> {
             [Token::Id(_), Token::Equal, ..] => self.parse_assignment()?,
             [Token::Id(id), ..] => {
                 self.advance_by(1);
-                Expression::Id(id.to_owned())
+                Expression::Id(Symbol::intern(id))
             }
             [Token::Literal(l), ..] => {
                 self.advance_by(1);
                 match l {
                     Literal::Integer(i) => Expression::Value(Value::Integer(*i)),
+                    Literal::Long(l) => Expression::Value(Value::Long(*l)),
                     Literal::Float(f) => Expression::Value(Value::Float(*f)),
                     Literal::String(s) => Expression::Value(Value::String(s.to_owned())),
                 }
@@ -165,12 +258,42 @@ impl<'a> Parser<'a> {
                 Expression::Value(Value::Boolean(false))
             }
             // [Token::LeftParenthesis, Token::Operator(_), ..] => self.parse_operation()?,
+            [Token::ExclamationMark, ..] => {
+                self.advance_by(1);
+                let operand = self.parse_expression()?;
+                Expression::UnaryOp(UnaryOperator::Not, Box::new(operand))
+            }
+            [Token::LeftParenthesis, Token::RightParenthesis, ..] => {
+                self.advance_by(2);
+                Expression::Value(Value::Unit)
+            }
             [Token::LeftParenthesis, _, ..] => self.parse_function_call()?,
             [Token::LeftBrace, ..] => self.parse_scope()?,
+            [Token::LeftSquareBracket, ..] => self.parse_list()?,
             [Token::Keyword(Keyword::Fn), ..] => self.parse_function()?,
             [Token::Keyword(Keyword::Let), ..] => self.parse_declaration()?,
             [Token::Keyword(Keyword::If), ..] => self.parse_condition()?,
             [Token::Keyword(Keyword::While), ..] => self.parse_while_loop()?,
+            [Token::Keyword(Keyword::Do), ..] => self.parse_do_while_loop()?,
+            [Token::Keyword(Keyword::Match), ..] => self.parse_match()?,
+            [Token::Keyword(Keyword::Import), Token::Literal(Literal::String(path)), ..] => {
+                self.advance_by(2);
+                Expression::Import(path.to_owned())
+            }
+            [Token::Keyword(Keyword::Import)] => return Err(UnexpectedEOF),
+            [Token::Keyword(Keyword::Import), ..] => return Err(UnexpectedToken(self.token_indices[1].clone())),
+            [Token::Keyword(Keyword::Break), ..] if self.loop_depth == 0 => return Err(BreakOutsideLoop(self.token_indices[0].clone())),
+            [Token::Keyword(Keyword::Break), ..] => {
+                self.advance_by(1);
+                Expression::Break
+            }
+            [Token::Keyword(Keyword::Continue), ..] if self.loop_depth == 0 => return Err(ContinueOutsideLoop(self.token_indices[0].clone())),
+            [Token::Keyword(Keyword::Continue), ..] => {
+                self.advance_by(1);
+                Expression::Continue
+            }
+            // An else is consumed by parse_condition, so this one has no if before it
+            [Token::Keyword(Keyword::Else), ..] => return Err(DanglingElse(self.token_indices[0].clone())),
             [_, ..] => return Err(UnexpectedToken(self.token_indices[0].clone())),
             [] => return Err(UnexpectedEOF),
         };
@@ -208,8 +331,12 @@ impl<'a> Parser<'a> {
                     self.advance_by(1);
                     let end_index = self.utf8_end_index;
 
+                    let label = Symbol::intern(id);
+                    if parameters.iter().any(|p: &Label| p.label == label) {
+                        return Err(DuplicateParameter(start_index..end_index));
+                    }
                     parameters.push(Label {
-                        label: id.to_owned(),
+                        label,
                         span: start_index..end_index,
                     });
                 }
@@ -220,12 +347,15 @@ impl<'a> Parser<'a> {
                 _ => return Err(UnexpectedToken(self.token_indices[0].clone())),
             }
         }
+        // A loop around the definition can't be stopped from the function's body
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
         let body = Box::new(self.parse_expression()?);
+        self.loop_depth = loop_depth;
 
         Ok(match name {
             Some(name) => Expression::NamedFunctionDefinition {
                 name: Label {
-                    label: name.to_owned(),
+                    label: Symbol::intern(name),
                     span: name_start_index..name_end_index,
                 },
                 parameters,
@@ -262,7 +392,7 @@ impl<'a> Parser<'a> {
         let rhs = self.parse_expression()?;
 
         Ok(Expression::Declaration(Label {
-            label: name.to_owned(),
+            label: Symbol::intern(name),
             span: name_start_index..name_end_index,
         }, Box::new(rhs)))
     }
@@ -285,12 +415,13 @@ impl<'a> Parser<'a> {
         let rhs = self.parse_expression()?;
 
         Ok(Expression::Assignment(Label {
-            label: name.to_owned(),
+            label: Symbol::intern(name),
             span: name_start_index..name_end_index,
         }, Box::new(rhs)))
     }
 
     fn parse_function_call(&mut self) -> Result<Expression, ParserError> {
+        let start_index = self.utf8_start_index;
         match self.tokens.first().ok_or(UnexpectedEOF)? {
             Token::LeftParenthesis => (),
             _ => return Err(UnexpectedToken(self.token_indices[0].clone())),
@@ -312,7 +443,25 @@ impl<'a> Parser<'a> {
             }
         }
 
-        Ok(Expression::FunctionCall(Box::new(function_ptr), arguments))
+        if let Expression::Id(id) = &function_ptr.expression {
+            if let Some(arity) = operator_arity(id.as_str()) {
+                if !arity.contains(&arguments.len()) {
+                    return Err(WrongOperatorArity {
+                        span: start_index..self.utf8_end_index,
+                        operator: id.to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok(match (&function_ptr.expression, arguments.as_mut_slice()) {
+            // A parenthesized condition is the condition, so it can be used as an operand: (+ 1 (if c 2))
+            (Expression::If { .. } | Expression::IfElse { .. }, []) => function_ptr.expression,
+            (Expression::Id(id), [_]) if id.as_str() == "-" => {
+                Expression::UnaryOp(UnaryOperator::Negate, Box::new(arguments.pop().unwrap()))
+            }
+            _ => Expression::FunctionCall(Box::new(function_ptr), arguments),
+        })
     }
 
     fn parse_scope(&mut self) -> Result<Expression, ParserError> {
@@ -335,6 +484,26 @@ impl<'a> Parser<'a> {
         Ok(Scope(expressions))
     }
 
+    fn parse_list(&mut self) -> Result<Expression, ParserError> {
+        match self.tokens.first().ok_or(UnexpectedEOF)? {
+            Token::LeftSquareBracket => (),
+            _ => return Err(UnexpectedToken(self.token_indices[0].clone())),
+        }
+        self.advance_by(1);
+
+        let mut elements = vec![];
+        loop {
+            match self.tokens.first().ok_or(UnexpectedEOF)? {
+                Token::RightSquareBracket => {
+                    self.advance_by(1);
+                    break;
+                }
+                _ => elements.push(self.parse_expression()?)
+            }
+        }
+        Ok(Expression::List(elements))
+    }
+
     fn parse_condition(&mut self) -> Result<Expression, ParserError> {
         match self.tokens.first().ok_or(UnexpectedEOF)? {
             Token::Keyword(Keyword::If) => (),
@@ -345,6 +514,8 @@ impl<'a> Parser<'a> {
         let guard = self.parse_expression()?;
         let base_case = self.parse_expression()?;
 
+        // "else if" needs no braces: the else case is just another if expression,
+        // so a chain of them produces right-nested IfElse
         let else_case_exists = match self.tokens.first() {
             Some(Token::Keyword(Keyword::Else)) => {
                 self.advance_by(1);
@@ -380,11 +551,209 @@ impl<'a> Parser<'a> {
         self.advance_by(1);
 
         let guard = self.parse_expression()?;
+        self.loop_depth += 1;
         let body = self.parse_expression()?;
+        self.loop_depth -= 1;
 
         Ok(Expression::While {
             guard: Box::new(guard),
             body: Box::new(body),
         })
     }
-}
\ No newline at end of file
+
+    fn parse_do_while_loop(&mut self) -> Result<Expression, ParserError> {
+        match self.tokens.first().ok_or(UnexpectedEOF)? {
+            Token::Keyword(Keyword::Do) => (),
+            _ => return Err(UnexpectedToken(self.token_indices[0].clone())),
+        }
+        self.advance_by(1);
+
+        self.loop_depth += 1;
+        let body = self.parse_expression()?;
+        self.loop_depth -= 1;
+
+        match self.tokens.first().ok_or(UnexpectedEOF)? {
+            Token::Keyword(Keyword::While) => (),
+            _ => return Err(UnexpectedToken(self.token_indices[0].clone())),
+        }
+        self.advance_by(1);
+
+        let guard = self.parse_expression()?;
+
+        Ok(Expression::DoWhile {
+            body: Box::new(body),
+            guard: Box::new(guard),
+        })
+    }
+
+    fn parse_match(&mut self) -> Result<Expression, ParserError> {
+        match self.tokens.first().ok_or(UnexpectedEOF)? {
+            Token::Keyword(Keyword::Match) => (),
+            _ => return Err(UnexpectedToken(self.token_indices[0].clone())),
+        }
+        self.advance_by(1);
+
+        let scrutinee = self.parse_expression()?;
+
+        match self.tokens.first().ok_or(UnexpectedEOF)? {
+            Token::LeftBrace => (),
+            _ => return Err(UnexpectedToken(self.token_indices[0].clone())),
+        }
+        self.advance_by(1);
+
+        let mut arms = Vec::new();
+        loop {
+            let pattern = match self.tokens.first().ok_or(UnexpectedEOF)? {
+                Token::RightBrace => {
+                    self.advance_by(1);
+                    break;
+                }
+                Token::Id(id) if id == "_" => Pattern::Wildcard,
+                Token::Literal(Literal::Integer(i)) => Pattern::Value(Value::Integer(*i)),
+                Token::Literal(Literal::Long(l)) => Pattern::Value(Value::Long(*l)),
+                Token::Literal(Literal::Float(f)) => Pattern::Value(Value::Float(*f)),
+                Token::Literal(Literal::String(s)) => Pattern::Value(Value::String(s.to_owned())),
+                Token::Keyword(Keyword::True) => Pattern::Value(Value::Boolean(true)),
+                Token::Keyword(Keyword::False) => Pattern::Value(Value::Boolean(false)),
+                _ => return Err(UnexpectedToken(self.token_indices[0].clone())),
+            };
+            self.advance_by(1);
+
+            match self.tokens.first().ok_or(UnexpectedEOF)? {
+                Token::FatArrow => (),
+                _ => return Err(UnexpectedToken(self.token_indices[0].clone())),
+            }
+            self.advance_by(1);
+
+            let body = self.parse_expression()?;
+            arms.push(MatchArm { pattern, body });
+        }
+
+        Ok(Expression::Match {
+            scrutinee: Box::new(scrutinee),
+            arms,
+        })
+    }
+}
+
+/// Number of arguments accepted by the built-in operators, checked when they are called by name.
+pub fn operator_arity(operator: &str) -> Option<RangeInclusive<usize>> {
+    match operator {
+        "!" => Some(1..=1),
+        "+" | "-" | "*" | "/" | "%" | "**" | "&&" | "||" => Some(1..=usize::MAX),
+        // Comparisons accept any number of arguments, see create_global_context_with_native_functions
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Lexer;
+
+    use super::*;
+
+    fn parse(source: &str) -> Result<Vec<ExpressionWithMetadata>, ParserError> {
+        let (tokens, indices) = Lexer::new(source).tokenize().unwrap();
+        Parser::new((tokens.as_slice(), indices.as_slice())).parse()
+    }
+
+    #[test]
+    fn duplicate_parameters() {
+        assert!(matches!(parse("fn f (x y x) x"), Err(DuplicateParameter(span)) if span == (10..11)));
+        assert!(matches!(parse("fn (a a) a"), Err(DuplicateParameter(span)) if span == (6..7)));
+        assert!(parse("fn f (x y) fn (x) x").is_ok());
+    }
+
+    #[test]
+    fn match_arms() {
+        let expressions = parse(r#"match x { 1 => "one" "two" => 2 _ => {} }"#).unwrap();
+        match &expressions[0].expression {
+            Expression::Match { scrutinee, arms } => {
+                assert!(matches!(scrutinee.expression, Expression::Id(id) if id.as_str() == "x"));
+                assert_eq!(arms.len(), 3);
+                assert!(matches!(arms[0].pattern, Pattern::Value(Value::Integer(1))));
+                assert!(matches!(&arms[1].pattern, Pattern::Value(Value::String(s)) if s == "two"));
+                assert!(matches!(arms[2].pattern, Pattern::Wildcard));
+                assert!(matches!(&arms[2].body.expression, Expression::Scope(body) if body.is_empty()));
+            }
+            expression => panic!("expected a match, got {:?}", expression),
+        }
+        assert!(matches!(parse("match x { y => 1 }"), Err(UnexpectedToken(span)) if span == (10..11)));
+        assert!(matches!(parse("match x { 1 2 }"), Err(UnexpectedToken(span)) if span == (12..13)));
+    }
+
+    #[test]
+    fn else_if_chains_nest_to_the_right() {
+        let source = "if a {1} else if b {2} else {3}";
+        let expressions = parse(source).unwrap();
+        assert_eq!(expressions[0].span, 0..31);
+        match &expressions[0].expression {
+            Expression::IfElse { guard, base_case, else_case } => {
+                assert_eq!(guard.span, 3..4);
+                assert_eq!(base_case.span, 5..8);
+                assert_eq!(&source[else_case.span.clone()], "if b {2} else {3}");
+                match &else_case.expression {
+                    Expression::IfElse { guard, base_case, else_case } => {
+                        assert_eq!(&source[guard.span.clone()], "b");
+                        assert_eq!(&source[base_case.span.clone()], "{2}");
+                        assert_eq!(&source[else_case.span.clone()], "{3}");
+                    }
+                    expression => panic!("expected an if else, got {:?}", expression),
+                }
+            }
+            expression => panic!("expected an if else, got {:?}", expression),
+        }
+    }
+
+    #[test]
+    fn dangling_else() {
+        assert!(matches!(parse("let x = 1 else {2}"), Err(DanglingElse(span)) if span == (10..14)));
+        assert!(matches!(parse("if a {1} else {2} else {3}"), Err(DanglingElse(span)) if span == (18..22)));
+    }
+
+    #[test]
+    fn break_and_continue_outside_loops() {
+        assert!(matches!(parse("break"), Err(BreakOutsideLoop(span)) if span == (0..5)));
+        assert!(matches!(parse("if true { continue }"), Err(ContinueOutsideLoop(span)) if span == (10..18)));
+        // A function body isn't in the loop it is declared in
+        assert!(matches!(parse("while true { fn f () break }"), Err(BreakOutsideLoop(_))));
+        assert!(parse("while true { if false { continue } break }").is_ok());
+    }
+
+    #[test]
+    fn unary_operators() {
+        let expressions = parse("(- 5) !done (- 5 1)").unwrap();
+        assert!(matches!(&expressions[0].expression, Expression::UnaryOp(UnaryOperator::Negate, operand)
+            if matches!(operand.expression, Expression::Value(Value::Integer(5)))));
+        assert!(matches!(&expressions[1].expression, Expression::UnaryOp(UnaryOperator::Not, operand)
+            if matches!(operand.expression, Expression::Id(id) if id.as_str() == "done")));
+        assert!(matches!(expressions[2].expression, Expression::FunctionCall(..)));
+    }
+
+    #[test]
+    fn operator_arity_is_checked() {
+        assert!(matches!(parse("(+)"), Err(WrongOperatorArity { span, operator }) if span == (0..3) && operator == "+"));
+        assert!(matches!(parse("(! a b)"), Err(WrongOperatorArity { span, operator }) if span == (0..7) && operator == "!"));
+        assert!(matches!(parse("(!)"), Err(WrongOperatorArity { .. })));
+        assert!(parse("(+ 1) (! a) (<) (== 1)").is_ok());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let expressions = parse(r#"
+            import "lib.rsp"
+            fn f (x) match x { 1 => "one" _ => [x 2.5 3L true ()] }
+            let y = !(f 1)
+            do { y = (- y) } while false
+        "#).unwrap();
+        let json = ast_to_json(&expressions).unwrap();
+        assert_eq!(dump_ast(&ast_from_json(&json).unwrap()), dump_ast(&expressions));
+
+        // Function values hold their context, they can't be serialized
+        let context = crate::create_global_context_with_native_functions();
+        let print = context.borrow().variables[&Symbol::intern("print")].clone();
+        let expressions = [ExpressionWithMetadata { expression: Expression::Value(print), span: 0..5 }];
+        assert!(ast_to_json(&expressions).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use std::cell::{RefCell, RefMut};
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use codespan_reporting::files::SimpleFiles;
+
+use crate::errors::{flush_diagnostics, show_interpreter_error, show_lexer_error, show_parser_error};
+use crate::interpreter::{InterpreterError, InterpreterErrorWithSpan};
+use crate::lexer::Lexer;
+use crate::native_functions::create_global_context_with_native_functions;
+use crate::parser::{Context, Parser, Value};
+
+/// Reads expressions from `input` until EOF, evaluating them in a single global context.
+/// An entry can span multiple lines: we keep reading while there are unclosed brackets.
+/// Values other than `()` are printed to `output`, errors are reported like in a script and
+/// don't end the session. Returns the status code given to `exit`, or 0 at EOF.
+/// The program reads and writes through `input` and `output` too, so `input` gets the lines
+/// following the one calling it.
+pub fn run_repl(input: impl BufRead + 'static, output: impl Write + 'static) -> io::Result<i32> {
+    let global_context = create_global_context_with_native_functions();
+    global_context.borrow_mut().input = Some(Box::new(input));
+    global_context.borrow_mut().output = Some(Box::new(output));
+
+    loop {
+        let mut source = String::new();
+        loop {
+            let mut output = output_of(&global_context);
+            write!(output, "{}", if source.is_empty() { "> " } else { "... " })?;
+            output.flush()?;
+            drop(output);
+
+            if input_of(&global_context).read_line(&mut source)? == 0 {
+                // EOF
+                writeln!(output_of(&global_context))?;
+                return Ok(0);
+            }
+            if bracket_depth(&source) <= 0 {
+                break;
+            }
+        }
+
+        let mut files = SimpleFiles::new();
+        let source_file = files.add("<repl>", &source);
+
+        let tokens_with_metadata = Lexer::new(&source).tokenize();
+        let tokens_with_metadata = match tokens_with_metadata {
+            Ok(t) => t,
+            Err(err) => {
+                show_lexer_error(err, source_file, files);
+                flush_diagnostics();
+                continue;
+            }
+        };
+
+        let expressions = Parser::new((tokens_with_metadata.0.as_slice(), tokens_with_metadata.1.as_slice())).parse();
+        let expressions = match expressions {
+            Ok(e) => e,
+            Err(err) => {
+                show_parser_error(err, source_file, files);
+                flush_diagnostics();
+                continue;
+            }
+        };
+
+        let mut result = Ok(Value::Unit);
+        for expression in &expressions {
+            result = expression.evaluate(global_context.clone());
+            if result.is_err() {
+                break;
+            }
+        }
+
+        match result {
+            Ok(Value::Unit) => (),
+            Ok(value) => writeln!(output_of(&global_context), "{}", value)?,
+            Err(InterpreterErrorWithSpan { error: InterpreterError::Exit(code), .. }) => {
+                output_of(&global_context).flush()?;
+                return Ok(code);
+            }
+            Err(err) => {
+                show_interpreter_error(err, source_file, files);
+                flush_diagnostics();
+            }
+        }
+    }
+}
+
+// The context must not stay borrowed while evaluating, the I/O functions borrow it too
+fn input_of(context: &Rc<RefCell<Context>>) -> RefMut<'_, dyn BufRead> {
+    RefMut::map(context.borrow_mut(), |context| context.input.as_deref_mut().unwrap())
+}
+
+fn output_of(context: &Rc<RefCell<Context>>) -> RefMut<'_, dyn Write> {
+    RefMut::map(context.borrow_mut(), |context| context.output.as_deref_mut().unwrap())
+}
+
+/// Number of brackets left open in `source`, ignoring the ones in strings and comments.
+fn bracket_depth(source: &str) -> i32 {
+    let mut depth = 0;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' if chars.peek() == Some(&'"') => { chars.next(); }
+                        '"' => break,
+                        _ => (),
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Collects what the REPL writes, it can still be read after the REPL has taken ownership.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Returns what was printed and the status code.
+    fn repl(input: &str) -> (String, i32) {
+        let output = SharedBuffer::default();
+        let code = run_repl(Cursor::new(input.to_owned()), output.clone()).unwrap();
+        let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+        (printed, code)
+    }
+
+    #[test]
+    fn evaluates_lines_in_a_persistent_context() {
+        let (printed, code) = repl("let x = 2\n(* x 21)\n(println \"hi\")\n");
+        assert_eq!(printed, "> > 42\n> hi\n> \n");
+        assert_eq!(code, 0);
+    }
+
+    #[test]
+    fn reads_until_brackets_are_closed() {
+        let (printed, _) = repl("fn f (x) {\n(+ x 1)\n}\n(f 1)\n");
+        assert_eq!(printed, "> ... ... > 2\n> \n");
+    }
+
+    #[test]
+    fn errors_dont_end_the_session() {
+        let (printed, code) = repl("(+ 1 true)\n)\n1\n");
+        assert_eq!(printed, "> > > 1\n> \n");
+        assert_eq!(code, 0);
+    }
+
+    #[test]
+    fn input_reads_the_next_line() {
+        let (printed, _) = repl("let name = (input \"name? \")\nBob\n(+ \"hi \" name)\n");
+        assert_eq!(printed, "> name? > hi Bob\n> \n");
+    }
+
+    #[test]
+    fn exit_returns_the_status_code() {
+        let (printed, code) = repl("(print \"bye\")\n(exit 3)\n1\n");
+        assert_eq!(printed, "> bye> ");
+        assert_eq!(code, 3);
+    }
+}
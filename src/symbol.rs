@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+/// An interned identifier.
+/// Comparing and hashing symbols is as cheap as for integers, which makes variable lookups fast.
+/// The strings are never freed, there are only as many as distinct identifiers in the program.
+/// The table is shared by all threads, so a symbol keeps its meaning on another thread.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+
+static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+
+fn interner() -> MutexGuard<'static, Interner> {
+    INTERNER.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Symbol {
+    pub fn intern(string: &str) -> Self {
+        let mut interner = interner();
+        if let Some(symbol) = interner.symbols.get(string) {
+            return *symbol;
+        }
+        let string: &'static str = Box::leak(string.to_owned().into_boxed_str());
+        let symbol = Symbol(interner.strings.len() as u32);
+        interner.strings.push(string);
+        interner.symbols.insert(string, symbol);
+        symbol
+    }
+
+    pub fn as_str(self) -> &'static str {
+        interner().strings[self.0 as usize]
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Symbol {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        Ok(Symbol::intern(&string))
+    }
+}
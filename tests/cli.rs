@@ -0,0 +1,54 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn write_script(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rusp-{}-{}.rsp", name, std::process::id()));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn rusp(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rusp")).args(args).output().unwrap()
+}
+
+#[test]
+fn check_fails_on_syntax_errors() {
+    let path = write_script("check-broken", "(println (+ 1 2)");
+    let output = rusp(&["--check", "--error-format", "json", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unexpected end of file"));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn check_doesnt_run_the_script() {
+    let path = write_script("check-valid", "let unused = 1 (println \"ran\")");
+    let output = rusp(&["--check", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unused variable 'unused'"));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn unknown_options_print_the_usage() {
+    let output = rusp(&["--frobnicate"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Usage: rusp"));
+}
+
+#[test]
+fn exit_in_a_nested_call_ends_the_script_with_its_code() {
+    let path = write_script("exit-nested", r#"
+        fn check (n) if (< n 0) { (println "negative") (exit 3) }
+        fn process (n) { (check n) (println "processed") }
+        (process 1)
+        (process -1)
+        (println "unreachable")
+    "#);
+    let output = rusp(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "processed\nnegative\n");
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::PathBuf;
+
+use rusp::interpreter::set_script_path;
+use rusp::{run, InterpreterError, RunError, Value};
+
+/// Writes the files in a new temporary directory and returns its path.
+fn write_files(test_name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("rusp-{}-{}", test_name, std::process::id()));
+    for (path, source) in files {
+        let path = directory.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+    directory
+}
+
+#[test]
+fn imported_functions_can_be_called() {
+    let directory = write_files("functions", &[
+        ("lib/math.rsp", "import \"square.rsp\" fn cube (x) (* x (square x))"),
+        ("lib/square.rsp", "fn square (x) (* x x)"),
+    ]);
+    set_script_path(&directory.join("main.rsp"));
+
+    let result = run("import \"lib/math.rsp\" (+ (cube 2) (square 3))");
+    assert!(matches!(result, Ok(Value::Integer(17))), "{:?}", result);
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn import_cycles_are_errors() {
+    let directory = write_files("cycle", &[
+        ("a.rsp", "import \"b.rsp\""),
+        ("b.rsp", "import \"a.rsp\""),
+    ]);
+    set_script_path(&directory.join("main.rsp"));
+
+    let mut result = run("import \"a.rsp\"");
+    // The import of a fails because the import of b fails, because b imports a again
+    let mut failed_imports = vec![];
+    while let Err(RunError::InterpreterError(err)) = result {
+        match err.error {
+            InterpreterError::ImportFailed { path, error, .. } => {
+                failed_imports.push(PathBuf::from(path).file_name().unwrap().to_owned());
+                result = Err(*error);
+            }
+            InterpreterError::ImportCycle(path) => {
+                assert!(path.ends_with("a.rsp"), "{}", path);
+                break;
+            }
+            error => panic!("expected an import cycle, got {:?}", error),
+        }
+    }
+    assert_eq!(failed_imports, vec!["a.rsp", "b.rsp"]);
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn importing_a_missing_file_is_an_error() {
+    let directory = write_files("missing", &[]);
+    set_script_path(&directory.join("main.rsp"));
+
+    let result = run("import \"nowhere.rsp\"");
+    assert!(matches!(result, Err(RunError::InterpreterError(ref e)) if matches!(e.error, InterpreterError::IoError(_))), "{:?}", result);
+}
@@ -0,0 +1,59 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use rusp::{create_global_context_with_native_functions, Lexer, Parser};
+
+/// Counts the allocations of the current thread, tests run in parallel.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Number of allocations made while evaluating `source`, lexing and parsing excluded.
+fn evaluation_allocations(source: &str) -> usize {
+    let (tokens, indices) = Lexer::new(source).tokenize().unwrap();
+    let expressions = Parser::new((tokens.as_slice(), indices.as_slice())).parse().unwrap();
+    let global_context = create_global_context_with_native_functions();
+
+    let before = ALLOCATIONS.with(Cell::get);
+    for expression in &expressions {
+        expression.evaluate(global_context.clone()).unwrap();
+    }
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn variable_lookups_dont_allocate() {
+    let baseline = evaluation_allocations("
+        let i = 0
+        let a_rather_long_variable_name = 1
+        while (< i 1000) {
+            i = (+ i a_rather_long_variable_name)
+        }
+    ");
+    let with_lookups = evaluation_allocations("
+        let i = 0
+        let a_rather_long_variable_name = 1
+        while (< i 1000) {
+            i = (+ i a_rather_long_variable_name)
+            a_rather_long_variable_name a_rather_long_variable_name a_rather_long_variable_name
+            a_rather_long_variable_name a_rather_long_variable_name a_rather_long_variable_name
+        }
+    ");
+    assert_eq!(baseline, with_lookups);
+}
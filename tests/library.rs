@@ -0,0 +1,56 @@
+use rusp::{run, set_recursion_limit, Function, InterpreterError, RunError, Value};
+
+#[test]
+fn run_returns_the_last_value() {
+    assert!(matches!(run("(+ 1 2)"), Ok(Value::Integer(3))));
+    assert!(matches!(run("let x = 2 (* x 3.5)"), Ok(Value::Float(x)) if x == 7.0));
+    assert!(matches!(run(""), Ok(Value::Unit)));
+}
+
+#[test]
+fn run_reports_each_kind_of_error() {
+    assert!(matches!(run("(+ 1 “2”)"), Err(RunError::LexerError(_))));
+    assert!(matches!(run("(+ 1 2"), Err(RunError::ParserError(_))));
+    let error = run("(+ 1 x)");
+    assert!(matches!(error, Err(RunError::InterpreterError(ref e)) if matches!(e.error, InterpreterError::VariableNotFound(_))), "{:?}", error);
+}
+
+#[test]
+fn assigning_an_undeclared_variable_is_an_error() {
+    let error = run("x = 1");
+    assert!(matches!(error, Err(RunError::InterpreterError(ref e)) if matches!(e.error, InterpreterError::VariableNotFound(_))), "{:?}", error);
+}
+
+#[test]
+fn run_evaluates_deep_recursions_on_a_small_stack() {
+    // Test threads have a 2 MiB stack, too small for the recursion limit without `run` spawning its own
+    let result = run("fn count (n) if (== n 0) { 0 } else { (+ 1 (count (- n 1))) } (count 900)");
+    assert!(matches!(result, Ok(Value::Integer(900))), "{:?}", result);
+
+    let result = run("fn forever (n) (forever n) (forever 0)");
+    assert!(matches!(result, Err(RunError::InterpreterError(ref e)) if matches!(e.error, InterpreterError::RecursionLimitExceeded)), "{:?}", result);
+}
+
+#[test]
+fn run_returns_functions_with_their_parameter_names() {
+    // The parameter names are interned on the evaluating thread
+    match run("fn add (first second) (+ first second) add") {
+        Ok(Value::Function(Function::RuspFunction { parameters, .. })) => {
+            let names: Vec<&str> = parameters.iter().map(|p| p.as_str()).collect();
+            assert_eq!(names, ["first", "second"]);
+        }
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn run_uses_the_recursion_limit_of_the_calling_thread() {
+    let count = "fn count (n) if (== n 0) { 0 } else { (+ 1 (count (- n 1))) } (count 2500)";
+    set_recursion_limit(3000);
+    let result = run(count);
+    assert!(matches!(result, Ok(Value::Integer(2500))), "{:?}", result);
+
+    set_recursion_limit(10);
+    let result = run(count);
+    assert!(matches!(result, Err(RunError::InterpreterError(ref e)) if matches!(e.error, InterpreterError::RecursionLimitExceeded)), "{:?}", result);
+}